@@ -1,23 +1,11 @@
 [
-  HandlerDefinition {
-    fluent_name: "high_speed_near_coast",
-    dependencies: &["high_speed", "near_coast"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let high_speed = fluents.get(0)?.value::<bool>();
-        let near_coast = fluents.get(1)?.value::<bool>();
-
-        usr::return_value(high_speed && near_coast)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "high_speed_near_coast_timer",
     dependencies: &["instant", "high_speed_near_coast"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -25,31 +13,13 @@
       }.boxed()
     ))
   },
-  HandlerDefinition {
-    fluent_name: "high_speed",
-    dependencies: &["speed"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let speed_fluent = fluents.get(0)?;
-
-        // optional check - fluents arrive in order given by dependency list
-        if speed_fluent.name() != "speed"
-           || !matches!(speed_fluent, Fluent::FloatPt(_)) {
-          panic!();
-        }
-
-        let speed = speed_fluent.value::<f64>();
-        usr::return_value(speed > 5.0)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "high_speed_timer",
     dependencies: &["instant", "high_speed"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -57,23 +27,13 @@
       }.boxed()
     ))
   },
-  HandlerDefinition {
-    fluent_name: "near_coast",
-    dependencies: &["distance_from_coast"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let distance_from_coast = fluents.get(0)?.value::<f64>();
-        usr::return_value(distance_from_coast <= 300.0)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "near_coast_timer",
     dependencies: &["instant", "near_coast"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -85,6 +45,7 @@
     fluent_name: "distance_from_coast",
     dependencies: &["location"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: Some(indoc! {r#"
       -- requires two input values: [lon, lat]
       select ST_Distance(
@@ -94,6 +55,7 @@
       from magritte.europe_coastline
       limit 1
     "#}),
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, database| async move {
         let (lon, lat) = fluents.get(0)?.value::<(f64, f64)>();
@@ -109,24 +71,27 @@
     fluent_name: "rendez_vous",
     dependencies: &["proximity" ,"rendez_vous_candidates"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let proximity_fluent = fluents.get(0)?;
-        let rendez_vous_candidates_fluent = fluents.get(1)?;
+      move |fluents, _| async move {
+        let proximity = fluents.get(0)?;
+        let rendez_vous_candidates = fluents.get(1)?;
+
+        // both need to have held for half an hour, as of the latest of them
+        let at = std::cmp::max(proximity.timestamp(),
+                               rendez_vous_candidates.timestamp());
 
-        let proximity = proximity_fluent.value::<bool>();
-        let rendez_vous_candidates =
-          rendez_vous_candidates_fluent.value::<bool>();
-        let last_change = std::cmp::max(
-          proximity_fluent.last_change(),
-          rendez_vous_candidates_fluent.last_change()
-        );
-        let int_dur_greater =
-          (proximity_fluent.timestamp() - last_change) > 1800;
+        let held = |interval: Option<&Interval>| {
+          interval.is_some_and(|interval| {
+            interval.end.is_none() && interval.duration(at) > 1800 * per_second
+          })
+        };
 
         usr::return_value(
-          proximity && rendez_vous_candidates && int_dur_greater
+          held(holds_for(proximity).last())
+          && held(holds_for(rendez_vous_candidates).last())
         )
       }.boxed()
     )),
@@ -135,7 +100,9 @@
     fluent_name: "rendez_vous_timer",
     dependencies: &["instant_keypair", "rendez_vous"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -143,23 +110,13 @@
       }.boxed()
     ))
   },
-  HandlerDefinition {
-    fluent_name: "proximity",
-    dependencies: &["distance"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let distance = fluents.get(0)?.value::<f64>();
-        usr::return_value(distance <= 100.0)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "proximity_timer",
     dependencies: &["instant_keypair", "proximity"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -170,8 +127,18 @@
   HandlerDefinition {
     fluent_name: "distance",
     dependencies: &["location"],
-    key_dependency: KeyDependency::NonConcurrent { timeout: 600 },
+    // only vessels within 10 km of each other are paired up
+    key_dependency: KeyDependency::NonConcurrent {
+      timeout: 600,
+      pairing: Some(Pairing {
+        dependency: "location".to_owned(),
+        radius: 10_000.0,
+        coordinates: Coordinates::Geographic,
+      }),
+    },
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let (lon_a, lat_a) = fluents.get(0)?.value::<(f64, f64)>();
@@ -206,7 +173,9 @@
     fluent_name: "location",
     dependencies: &["lon", "lat"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let lon = fluents.get(0)?.value::<f64>();
@@ -216,62 +185,11 @@
       }.boxed()
     )),
   },
-  HandlerDefinition {
-    fluent_name: "rendez_vous_candidates",
-    dependencies: &["rendez_vous_conditions"],
-    key_dependency: KeyDependency::NonConcurrent { timeout: 1800 },
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let lhs_is_candidate = fluents.get(0)?.value::<bool>();
-        let rhs_is_candidate = fluents.get(1)?.value::<bool>();
-
-        usr::return_value(lhs_is_candidate && rhs_is_candidate)
-      }.boxed()
-    )),
-  },
-  HandlerDefinition {
-    fluent_name: "rendez_vous_conditions",
-    dependencies: &[
-      "stopped_or_low_speed",
-      "is_tug_or_pilot",
-      "near_coast",
-      "near_ports"
-    ],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let stopped_or_low_speed = fluents.get(0)?.value::<bool>();
-        let is_tug_or_pilot = fluents.get(1)?.value::<bool>();
-        let near_coast = fluents.get(2)?.value::<bool>();
-        let near_ports = fluents.get(3)?.value::<bool>();
-
-        usr::return_value(
-          stopped_or_low_speed
-          && !is_tug_or_pilot
-          && !near_coast
-          && !near_ports
-        )
-      }.boxed()
-    )),
-  },
-  HandlerDefinition {
-    fluent_name: "stopped_or_low_speed",
-    dependencies: &["speed"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let speed = fluents.get(0)?.value::<f64>();
-        usr::return_value(speed <= 5.0)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "is_tug_or_pilot",
     dependencies: &["speed"],
     key_dependency: KeyDependency::Static,
+    alignment: Alignment::Exact,
     database_query: Some(indoc! {r#"
       -- requires one input value: [key]
       select shiptype as ship_type
@@ -279,9 +197,10 @@
       where sourcemmsi = $1
       limit 1
     "#}),
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, database| async move {
-        let key = fluents.get(0)?.keys().first()?.to_owned() as i32;
+        let key = fluents.get(0)?.keys().first()?.as_integer()? as i32;
         let ship_type = database.query::<i32>(&[&key]).await?;
 
         // Vessel Type Codes:
@@ -304,7 +223,9 @@
     fluent_name: "is_tug_or_pilot_timer",
     dependencies: &["instant", "is_tug_or_pilot"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let instant = fluents.get(0)?.value::<std::time::Instant>();
@@ -312,22 +233,11 @@
       }.boxed()
     ))
   },
-  HandlerDefinition {
-    fluent_name: "near_ports",
-    dependencies: &["distance_from_ports"],
-    key_dependency: KeyDependency::Concurrent,
-    database_query: None,
-    eval_fn: EvalFn::specify(Arc::new(
-      |fluents, _| async move {
-        let distance_from_ports = fluents.get(0)?.value::<f64>();
-        usr::return_value(distance_from_ports <= 300.0)
-      }.boxed()
-    )),
-  },
   HandlerDefinition {
     fluent_name: "distance_from_ports",
     dependencies: &["location"],
     key_dependency: KeyDependency::Concurrent,
+    alignment: Alignment::Exact,
     database_query: Some(indoc! {r#"
       -- requires two input values: [lon, lat]
       select ST_Distance(
@@ -338,6 +248,7 @@
       order by distance
       limit 1
     "#}),
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, database| async move {
         let (lon, lat) = fluents.get(0)?.value::<(f64, f64)>();
@@ -352,8 +263,13 @@
   HandlerDefinition {
     fluent_name: "instant_keypair",
     dependencies: &["instant"],
-    key_dependency: KeyDependency::NonConcurrent { timeout: 30 },
+    key_dependency: KeyDependency::NonConcurrent {
+      timeout: 30,
+      pairing: None,
+    },
+    alignment: Alignment::Exact,
     database_query: None,
+    emit: Emit::Always,
     eval_fn: EvalFn::specify(Arc::new(
       |fluents, _| async move {
         let lhs = fluents.get(0)?.value::<std::time::Instant>();
@@ -6,19 +6,36 @@
 
 use super::{broker::Broker,
             database::Database,
+            graph::{FluentGraph, GraphFormat, NodeKind},
+            node::Node,
+            reload::RuleReload,
             sink::Sink,
             source::Source,
             util};
-use crate::{fluent::{Fluent, FluentTrait, ValueType},
-            handler::{EvalFn, Handler, HandlerDefinition, KeyDependency}};
+use crate::{fluent::{FluentTrait, TimeUnit, ValueType},
+            handler::{holds_for,
+                      Alignment,
+                      Coordinates,
+                      Emit,
+                      EvalFn,
+                      Handler,
+                      HandlerDefinition,
+                      Interval,
+                      KeyDependency,
+                      Lateness,
+                      Pairing,
+                      Rule,
+                      Window}};
 
 use clap::Parser;
 use eyre::Result;
 use futures::future::FutureExt;
 use indoc::indoc;
 use serde::Deserialize;
-use std::{fs, sync::Arc};
-use tracing::{debug, info};
+use std::{fs,
+          sync::{atomic::Ordering, Arc}};
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::{debug, error, info, warn};
 
 
 #[derive(Debug, Deserialize)]
@@ -26,11 +43,25 @@ use tracing::{debug, info};
 /// From here, all core elements and fluent [`Handler`]s are initialized and
 /// put into operation via the `run` method.
 pub struct AppCore {
-  database:       Database,
-  broker:         Broker,
-  source:         Source,
-  sinks:          Vec<Sink>,
-  buffer_timeout: usize,
+  database:         Database,
+  broker:           Broker,
+  source:           Source,
+  sinks:            Vec<Sink>,
+  #[serde(default)]
+  windows:          Vec<Window>,
+  buffer_timeout:   usize,
+  #[serde(default)]
+  lateness:         Lateness,
+  #[serde(default)]
+  rules_path:       Option<String>,
+  #[serde(default)]
+  rules_reload:     Option<u64>,
+  #[serde(default)]
+  allow_unconsumed: bool,
+  #[serde(skip)]
+  export_graph:     Option<GraphFormat>,
+  #[serde(skip)]
+  shutdown:         Arc<Notify>,
 }
 
 impl AppCore {
@@ -38,28 +69,91 @@ impl AppCore {
   /// parameters are parsed from a (required) config file.
   pub fn init() -> Result<Self> {
     let args = util::CommandLineArgs::parse();
-    let app_init: Self =
+    let mut app_init: Self =
       toml::from_str(&fs::read_to_string(args.config_path)?)?;
+    app_init.source.validate()?;
+    for window in &app_init.windows {
+      window.validate()?;
+    }
+    for sink in &app_init.sinks {
+      sink.validate()?;
+    }
+    app_init.export_graph = args.export_graph;
 
     Ok(app_init)
   }
 
-  /// Prepares the database for a run using the following PostgreSQL:
+  /// Returns a handle to shut down a run in an orderly manner: notifying it
+  /// stops the [`Source`], after which everything it published is processed
+  /// and written before [`AppCore::run`] returns.
+  pub fn shutdown_trigger(&self) -> Arc<Notify> {
+    self.shutdown.clone()
+  }
+
+  /// Checks that the fluents published and subscribed to by all nodes fit
+  /// together, then prepares the database for a run using the following
+  /// PostgreSQL:
   ///
   /// ```sql
   #[doc = include_str!("./sql/prepare_run.sql")]
   /// ```
   ///
   /// Furthermore, registers the [`Source`], [`Sink`] and fluent nodes at the
-  /// broker and makes `magritte` ready to run. Finally - runs the application.
-  /// Consumes the `AppCore` object.
+  /// broker and makes `magritte` ready to run. Finally - runs the application
+  /// until everything the [`Source`] has published has been processed and
+  /// written. Consumes the `AppCore` object.
   pub async fn run(self) -> Result<()> {
     // decompose self into contained handles
     let Self { database,
                mut broker,
                mut source,
                mut sinks,
-               buffer_timeout, } = self;
+               windows,
+               buffer_timeout,
+               lateness,
+               rules_path,
+               rules_reload,
+               allow_unconsumed,
+               export_graph,
+               shutdown, } = self;
+
+    // timeouts are given in seconds, timestamps in the unit of the source
+    let time_unit = source.timestamp_unit();
+    let buffer_timeout = buffer_timeout * time_unit.per_second();
+
+    let handlers =
+      handlers(buffer_timeout, time_unit, &lateness, &database).await?;
+    let rules = match &rules_path {
+      Some(path) => Rule::load(path)?,
+      None => Vec::new(),
+    };
+    let mut rule_handlers = Vec::new();
+    for rule in rules.iter() {
+      rule_handlers.push(rule.handler(buffer_timeout,
+                                      time_unit,
+                                      lateness.clone(),
+                                      database.clone())
+                             .await?);
+    }
+    let late_counts = handlers.iter()
+                              .chain(rule_handlers.iter())
+                              .map(|h| (h.name(), h.late_fluents()))
+                              .collect::<Vec<_>>();
+
+    // only print the graph of all nodes if that's what was asked for;
+    // otherwise check that all nodes fit together before anything is run
+    let mut graph = graph(&source,
+                          handlers.iter().chain(rule_handlers.iter()),
+                          &windows,
+                          &sinks);
+    if allow_unconsumed {
+      graph.allow_unconsumed();
+    }
+    if let Some(format) = export_graph {
+      println!("{}", graph.render(format));
+      return Ok(());
+    }
+    graph.validate()?;
 
     // run prep
     let client = database.connect().await?;
@@ -72,6 +166,11 @@ impl AppCore {
     debug!("executing USER run preparation SQL:\n\n{}", sql_raw);
     client.batch_execute(sql_raw).await?;
 
+    // a live source may go quiet for a while without the run being over
+    if source.is_live() {
+      broker.keep_alive();
+    }
+
     broker.register(&mut source);
     for sink in sinks.iter_mut() {
       broker.register(sink);
@@ -79,56 +178,208 @@ impl AppCore {
 
     // initialize and run nodes
     let mut node_tasks = Vec::new();
-    for def in include!("../../conf/handler_definitions.rs") {
-      let mut node =
-        Handler::new(def, buffer_timeout, database.clone()).await?;
-
+    for mut node in handlers {
       broker.register(&mut node);
+      node_tasks.push(tokio::spawn(node.run()));
+    }
+
+    for mut window in windows {
+      broker.register(&mut window);
+      node_tasks.push(tokio::spawn(window.run(lateness.allowed)));
+    }
 
-      let task = tokio::spawn(async move {
-        node.run().await.expect("Node has stopped");
-      });
-      node_tasks.push(task);
+    let mut rule_tasks = Vec::new();
+    for (rule, mut node) in rules.into_iter().zip(rule_handlers) {
+      broker.register(&mut node);
+      rule_tasks.push((rule, tokio::spawn(node.run())));
     }
 
-    // establish a database connection and create a database client, then start
-    // the sink task, creating a handle to the task.
+    // with a reload interval, the rule file is checked for changes while
+    // running, and the handlers of the rules in it started and retired
+    // accordingly - these are registered at the running broker
+    let reload_stop = Arc::new(Notify::new());
+    let reload_task = match (rules_path, rules_reload) {
+      (Some(path), Some(interval)) => {
+        let reload = RuleReload::new(path,
+                                     interval,
+                                     buffer_timeout,
+                                     time_unit,
+                                     lateness,
+                                     database.clone(),
+                                     broker.registrar(),
+                                     graph,
+                                     rule_tasks);
+        Some(tokio::spawn(reload.run(reload_stop.clone())))
+      }
+      _ => {
+        node_tasks.extend(rule_tasks.into_iter().map(|(_, task)| task));
+        None
+      }
+    };
+
+    // establish a database connection and create a database client if the
+    // sink writes to the database, then start the sink task, creating a handle
+    // to the task.
     info!("starting sink tasks...");
     let mut sink_tasks = Vec::new();
     for sink in sinks {
-      let sink_dbc = database.connect().await?;
-      let sink_task = tokio::spawn(async move {
-        sink.run(Some(sink_dbc)).await.expect("sink has stopped");
-      });
-      sink_tasks.push(sink_task);
+      let sink_dbc = match sink.requires_database() {
+        true => Some(database.connect().await?),
+        false => None,
+      };
+      sink_tasks.push(tokio::spawn(sink.run(sink_dbc)));
     }
 
-    // establish a database connection and create a database client, then start
-    // the source task, creating a handle to the task.
+    // establish a database connection and create a database client if the
+    // source reads from the database, then start the source task, creating a
+    // handle to the task.
     info!("starting source task...");
-    let source_dbc = database.connect().await?;
+    let source_dbc = match source.requires_database() {
+      true => Some(database.connect().await?),
+      false => None,
+    };
     let source_task = tokio::spawn(async move {
-      source.run(Some(source_dbc))
-            .await
-            .expect("source has stopped");
+      tokio::select! {
+        result = source.run(source_dbc) => result,
+        // stopping the source ends the stream, which is then drained
+        _ = shutdown.notified() => {
+          info!("source stopped for shutdown");
+          Ok(())
+        }
+      }
     });
 
     // start the broker task, creating a handle to the task.
     info!("starting broker...");
-    broker.run().await?;
+    let lag_report = broker.lag_report();
+    let unknown_fluents = broker.unknown_fluents();
+    // a task failing does not keep the others from finishing: the first error
+    // is returned once the sinks are done and everything has been reported
+    let mut result = broker.run().await;
+    let broker_failed = result.is_err();
+
+    reload_stop.notify_one();
+    if let Some(reload_task) = reload_task {
+      match reload_task.await {
+        Ok(tasks) => node_tasks.extend(tasks),
+        Err(err) => record(&mut result, Err(err.into())),
+      }
+    }
 
-    // if broker task has ended, abort all tasks.
-    info!("broker stopped, aborting all tasks...");
-    for node_task in node_tasks {
-      node_task.abort();
+    // if the broker has stopped because all nodes are done, these tasks are
+    // done as well. otherwise, abort all tasks but the sinks: these notice the
+    // broker has stopped and finish by writing what they have buffered.
+    match broker_failed {
+      false => {
+        info!("broker drained, waiting for all tasks to finish...");
+        record(&mut result, join(source_task).await);
+        for node_task in node_tasks {
+          record(&mut result, join(node_task).await);
+        }
+      }
+      true => {
+        info!("broker stopped, aborting all tasks...");
+        for node_task in node_tasks {
+          node_task.abort();
+        }
+        source_task.abort();
+      }
     }
+
+    info!("waiting for sinks to finish writing...");
     for sink_task in sink_tasks {
-      sink_task.abort();
+      record(&mut result, join(sink_task).await);
+    }
+
+    for (fluent_name, late_fluents) in late_counts {
+      let late_fluents = late_fluents.load(Ordering::Relaxed);
+      if late_fluents > 0 {
+        warn!("handler '{}' received {} late fluents",
+              fluent_name,
+              late_fluents);
+      }
+    }
+
+    // unwrap here is safe: locking `Mutex` cannot fail
+    for (fluent_name, count) in unknown_fluents.lock().unwrap().iter() {
+      warn!("received {} fluents named '{}', which no node was registered \
+             with",
+            count,
+            fluent_name);
+    }
+
+    for ((node_name, fluent_name), skipped) in lag_report.skipped() {
+      warn!("'{}' lagged behind on '{}' and missed {} fluents",
+            node_name,
+            fluent_name,
+            skipped);
+    }
+
+    result
+  }
+}
+
+
+/// Awaits a task, turning a panic into an error like the ones returned.
+async fn join(task: JoinHandle<Result<()>>) -> Result<()> {
+  task.await?
+}
+
+/// Keeps the error of a task, unless the run has already failed, in which
+/// case it is only logged.
+fn record(result: &mut Result<()>, task_result: Result<()>) {
+  if let Err(err) = task_result {
+    match result {
+      Ok(()) => *result = Err(err),
+      Err(_) => error!("{:?}", err),
     }
-    source_task.abort();
+  }
+}
+
+
+/// Instantiates the [`Handler`]s defined in `conf/handler_definitions.rs`,
+/// for timestamps in `time_unit`. Durations in there are given in seconds,
+/// and scaled by `per_second` where they are compared to timestamps.
+async fn handlers(buffer_timeout: usize,
+                  time_unit: TimeUnit,
+                  lateness: &Lateness,
+                  database: &Database)
+                  -> Result<Vec<Handler>> {
+  let per_second = time_unit.per_second();
+  let mut handlers = Vec::new();
+  for def in include!("../../conf/handler_definitions.rs") {
+    let def = HandlerDefinition { key_dependency:
+                                    def.key_dependency.in_unit(time_unit),
+                                  ..def };
+    handlers.push(Handler::new(def,
+                               buffer_timeout,
+                               lateness.clone(),
+                               database.clone()).await?);
+  }
+  Ok(handlers)
+}
 
-    Ok(())
+
+/// Builds the publish/subscribe graph of all nodes, annotating handlers with
+/// their key dependency and whether they query the database, and windows with
+/// what they aggregate.
+fn graph<'a>(source: &Source,
+             handlers: impl Iterator<Item = &'a Handler>,
+             windows: &[Window],
+             sinks: &[Sink])
+             -> FluentGraph {
+  let mut graph = FluentGraph::new();
+  graph.add(NodeKind::Source, source);
+  for handler in handlers {
+    graph.add_handler(handler);
+  }
+  for window in windows {
+    graph.add_annotated(NodeKind::Handler, window, vec![window.description()]);
+  }
+  for sink in sinks {
+    graph.add(NodeKind::Sink, sink);
   }
+  graph
 }
 
 
@@ -146,11 +397,12 @@ mod usr {
 
 #[cfg(test)]
 mod tests {
-  use super::AppCore;
+  use super::{graph, handlers, join, record, AppCore, Rule};
   use crate::{app_core::node::{Node, NodeRx},
-              fluent::FluentTrait,
+              fluent::{FluentTrait, Key},
               stringvec};
 
+  use eyre::eyre;
   use pretty_assertions::assert_eq;
   use tokio::sync::mpsc;
 
@@ -166,7 +418,7 @@ mod tests {
     assert_eq!(source.subscribes_to(), Vec::<String>::new());
 
     let (tx, mut rx) = mpsc::unbounded_channel();
-    source.initialize(tx, NodeRx::new());
+    source.initialize(tx.into(), NodeRx::new());
 
     let database_client = app_core.database.connect().await.unwrap();
     let runner = tokio::spawn(async move {
@@ -179,7 +431,7 @@ mod tests {
     let fluent = rx.recv().await.unwrap();
 
     assert_eq!(fluent.name(), "lon");
-    assert_eq!(fluent.keys(), &[226338000]);
+    assert_eq!(fluent.keys(), &[Key::from(226338000)]);
     assert_eq!(fluent.timestamp(), 1443679200);
     assert_eq!(fluent.value::<f64>(), -5.160865);
     assert_eq!(fluent.last_change(), 1443679200);
@@ -199,4 +451,41 @@ mod tests {
     assert_eq!(source.subscribes_to(), Vec::<String>::new());
     assert!(source.run(Some(database_client)).await.is_err());
   }
+
+  #[tokio::test]
+  async fn graph_test() {
+    let app_core = AppCore::init().unwrap();
+
+    let time_unit = app_core.source.timestamp_unit();
+    let mut handlers = handlers(app_core.buffer_timeout,
+                                time_unit,
+                                &app_core.lateness,
+                                &app_core.database).await
+                                                   .unwrap();
+    for rule in Rule::load(&app_core.rules_path.unwrap()).unwrap() {
+      handlers.push(rule.handler(app_core.buffer_timeout,
+                                 time_unit,
+                                 app_core.lateness.clone(),
+                                 app_core.database.clone())
+                        .await
+                        .unwrap());
+    }
+    graph(&app_core.source,
+          handlers.iter(),
+          &app_core.windows,
+          &app_core.sinks).validate()
+                          .unwrap();
+  }
+
+  #[tokio::test]
+  async fn record_test() {
+    let mut result = Ok(());
+    record(&mut result, Ok(()));
+    assert!(result.is_ok());
+
+    let panicked = tokio::spawn(async { panic!("source has stopped") });
+    record(&mut result, join(panicked).await);
+    record(&mut result, Err(eyre!("sink has stopped")));
+    assert!(result.unwrap_err().to_string().contains("panicked"));
+  }
 }
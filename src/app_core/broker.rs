@@ -4,39 +4,136 @@
 // received a copy of this license along with the source code. If that is not
 // the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
 
-use super::node::{Node, NodeTx};
+use super::node::{LagPolicy, LagReport, Node, NodeRx, NodeTx};
 use crate::fluent::{Fluent, FluentTrait};
 
-use eyre::Result;
+use derivative::Derivative;
+use eyre::{bail, eyre, Result, WrapErr};
 use serde::Deserialize;
-use std::{collections::HashMap, time::Duration};
-use tokio::{sync::{broadcast, mpsc},
+use std::{collections::{BTreeMap, HashMap, HashSet},
+          mem,
+          pin::Pin,
+          sync::{Arc, Mutex},
+          time::Duration};
+use tokio::{sync::{broadcast, mpsc, oneshot},
             time};
-use tokio_stream::StreamMap;
+use tokio_stream::{self as stream,
+                   wrappers::{ReceiverStream, UnboundedReceiverStream},
+                   Stream,
+                   StreamExt,
+                   StreamMap};
 // use tracing::info;
+use tracing::warn;
 
 
-#[derive(Debug, Deserialize)]
+/// Stream of the fluents a [`Node`] publishes, ending with `None` once the
+/// [`Node`] is done, i.e. all of its [`NodeTx`] have been dropped.
+type Input = Pin<Box<dyn Stream<Item = Option<Fluent>> + Send>>;
+
+
+#[derive(Debug)]
+/// Request to register a [`Node`] at a running [`Broker`], which replies with
+/// the [`NodeTx`] and [`NodeRx`] to initialize the [`Node`] with.
+struct Registration {
+  name:          String,
+  publishes:     Vec<String>,
+  subscribes_to: Vec<String>,
+  reply:         oneshot::Sender<(NodeTx, NodeRx)>,
+}
+
+
+#[derive(Clone, Debug)]
+/// Handle to register [`Node`]s at a [`Broker`] while it is running.
+pub struct Registrar {
+  tx: mpsc::UnboundedSender<Registration>,
+}
+
+impl Registrar {
+  /// Registers a [`Node`] like [`Broker::register`], once the [`Broker`] is
+  /// running. Fails if the [`Broker`] has stopped.
+  pub async fn register<T: Node + ?Sized>(&self, node: &mut T) -> Result<()> {
+    let (reply, rx) = oneshot::channel();
+    self.tx
+        .send(Registration { name: node.name(),
+                             publishes: node.publishes(),
+                             subscribes_to: node.subscribes_to(),
+                             reply })
+        .map_err(|_| eyre!("broker has stopped"))?;
+    let (node_tx, node_rx) = rx.await.wrap_err("broker has stopped")?;
+    node.initialize(node_tx, node_rx);
+    Ok(())
+  }
+}
+
+
+#[derive(Derivative, Deserialize)]
+#[derivative(Debug)]
 /// Core service of the application. Receives fluents from publisher nodes and
-/// forwards them to subscriber nodes efficiently.
+/// forwards them to subscriber nodes efficiently. Once all publishers of a
+/// fluent are done, its subscribers are notified by the channel closing, so
+/// the nodes of a run finish one after the other as their inputs are drained.
 pub struct Broker {
   broadcast_capacity: usize,
   timeout:            u64,
+  #[serde(default)]
+  lag_policy:         LagPolicy,
+  #[serde(default)]
+  input_capacity:     Option<usize>,
+  #[serde(default)]
+  create_unknown:     bool,
+  #[serde(skip)]
+  lag_report:         LagReport,
+  #[serde(skip)]
+  unknown_fluents:    Arc<Mutex<BTreeMap<String, u64>>>,
+  #[serde(skip)]
+  created_unknown:    HashSet<String>,
+  #[serde(skip)]
+  keep_alive:         bool,
   #[serde(skip)]
   fluents:            HashMap<String, broadcast::Sender<Fluent>>,
-  #[serde(skip, default = "mpsc::unbounded_channel")]
-  node_ch:            (NodeTx, mpsc::UnboundedReceiver<Fluent>),
+  #[serde(skip)]
+  publishers:         HashMap<String, usize>,
+  #[serde(skip)]
+  node_publishes:     Vec<Vec<String>>,
+  #[serde(skip)]
+  #[derivative(Debug = "ignore")]
+  inputs:             StreamMap<usize, Input>,
+  #[serde(skip)]
+  #[derivative(Debug = "ignore")]
+  parked:             HashMap<usize, (Fluent, Input)>,
+  #[serde(skip)]
+  registrations:      Option<mpsc::UnboundedReceiver<Registration>>,
 }
 
 impl Broker {
   /// Method to register a [`Node`] at the [`Broker`]. Creates channels to
   /// communicate fluents as required and initializes [`Node`]s accordingly.
   pub fn register<T: Node + ?Sized>(&mut self, node: &mut T) {
+    let (node_tx, node_rx) =
+      self.connect(node.name(), node.publishes(), node.subscribes_to());
+    node.initialize(node_tx, node_rx)
+  }
+
+  /// Returns a handle to register further [`Node`]s once the [`Broker`] is
+  /// running.
+  pub fn registrar(&mut self) -> Registrar {
+    let (tx, rx) = mpsc::unbounded_channel();
+    self.registrations = Some(rx);
+    Registrar { tx }
+  }
+
+  /// Creates the channels for a [`Node`] publishing and subscribing to the
+  /// given fluents.
+  fn connect(&mut self,
+             node_name: String,
+             publishes: Vec<String>,
+             subscribes_to: Vec<String>)
+             -> (NodeTx, NodeRx) {
     // add all fluents, whether published or subscribed to by the node, into
     // the known list of fluents, and create broadcast sender handles to them
-    for fluent_name in
-      node.publishes().iter().chain(node.subscribes_to().iter())
-    {
+    for fluent_name in publishes.iter().chain(subscribes_to.iter()) {
+      // a channel created for an unknown name is now known
+      self.created_unknown.remove(fluent_name);
       if !self.fluents.contains_key(fluent_name) {
         let (tx, _) = broadcast::channel(self.broadcast_capacity);
         self.fluents.insert(fluent_name.clone(), tx);
@@ -44,40 +141,217 @@ impl Broker {
     }
 
     // bundle all subscribed fluent receivers together in one stream
-    let mut stream_map = StreamMap::new();
-    for fluent_name in node.subscribes_to() {
-      stream_map.insert(fluent_name.clone(),
+    let mut node_rx =
+      NodeRx::with_lag(node_name, self.lag_policy, self.lag_report.clone());
+    for fluent_name in subscribes_to {
+      node_rx.subscribe(fluent_name.clone(),
                         self.fluents[&fluent_name].subscribe().into());
     }
 
-    // initialize Node with NodeTx and NodeRx
-    let node_tx = self.node_ch.0.clone();
-    node.initialize(node_tx, stream_map)
+    (self.input(publishes), node_rx)
+  }
+
+  /// Creates the channel a [`Node`] publishes the given fluents on, which is
+  /// bounded if an input capacity is configured.
+  fn input(&mut self, publishes: Vec<String>) -> NodeTx {
+    for fluent_name in publishes.iter() {
+      *self.publishers.entry(fluent_name.clone()).or_default() += 1;
+    }
+    self.node_publishes.push(publishes);
+
+    let (node_tx, input): (_, Pin<Box<dyn Stream<Item = _> + Send>>) =
+      match self.input_capacity {
+        Some(capacity) => {
+          let (tx, rx) = mpsc::channel(capacity);
+          (tx.into(), Box::pin(ReceiverStream::new(rx)))
+        }
+        None => {
+          let (tx, rx) = mpsc::unbounded_channel();
+          (tx.into(), Box::pin(UnboundedReceiverStream::new(rx)))
+        }
+      };
+    let input = input.map(Some).chain(stream::once(None));
+    self.inputs
+        .insert(self.node_publishes.len() - 1, Box::pin(input));
+
+    node_tx
+  }
+
+  /// Closes the channels of the fluents a [`Node`] published, unless another
+  /// [`Node`] still publishes them.
+  fn finish(&mut self, node: usize) {
+    for fluent_name in self.node_publishes[node].iter() {
+      if let Some(publishers) = self.publishers.get_mut(fluent_name) {
+        *publishers -= 1;
+        if *publishers == 0 {
+          self.fluents.remove(fluent_name);
+        }
+      }
+    }
+  }
+
+  /// Sends a fluent to the subscribers of its channel. Under
+  /// [`LagPolicy::Backpressure`], the fluent is handed back instead while the
+  /// slowest subscriber has no room for it.
+  fn forward(&self, fluent: Fluent) -> Result<Option<Fluent>> {
+    let Some(sender) = self.fluents.get(fluent.name()) else {
+      return Ok(None);
+    };
+
+    // sending on a broadcast channel may return an error Result, which just
+    // means that there are no receivers for this channel. however, it still
+    // takes resources and since CURRENTLY all our receivers are known at
+    // compile time, we can just skip that send entirely.
+    if sender.receiver_count() == 0 {
+      return Ok(None);
+    }
+    if self.lag_policy == LagPolicy::Backpressure
+       && sender.len() >= self.broadcast_capacity
+    {
+      return Ok(Some(fluent));
+    }
+
+    // now we can safely ? on the send; otherwise we could use a match here.
+    sender.send(fluent)?;
+    Ok(None)
+  }
+
+  /// Sends the fluents held back under [`LagPolicy::Backpressure`] whose
+  /// channels have made room, and reads on from the inputs they came from.
+  fn unpark(&mut self) -> Result<()> {
+    for (node, (fluent, input)) in mem::take(&mut self.parked) {
+      match self.forward(fluent)? {
+        Some(fluent) => {
+          self.parked.insert(node, (fluent, input));
+        }
+        None => {
+          self.inputs.insert(node, input);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Returns a handle to the record of fluents missed by [`Node`]s lagging
+  /// behind, to be read once the run is over.
+  pub fn lag_report(&self) -> LagReport {
+    self.lag_report.clone()
+  }
+
+  /// Returns a handle to the count of fluents received under names no
+  /// [`Node`] was registered with, by name, to be read once the run is over.
+  pub fn unknown_fluents(&self) -> Arc<Mutex<BTreeMap<String, u64>>> {
+    self.unknown_fluents.clone()
+  }
+
+  /// Keeps the [`Broker`] running even if no fluents are received for longer
+  /// than the configured timeout, e.g. when the source is following a live
+  /// data feed which may pause at any time.
+  pub fn keep_alive(&mut self) {
+    self.keep_alive = true;
   }
 
   /// Runs the [`Broker`], receiving fluents from [`Node`]s and forwarding them
-  /// to the  [`Node`]s which are subscribed to the respective fluent.
-  pub async fn run(self) -> Result<()> {
-    let mut input_rx = self.node_ch.1;
+  /// to the  [`Node`]s which are subscribed to the respective fluent, and
+  /// registering [`Node`]s sent by a [`Registrar`] on the way.
+  /// Ends once all [`Node`]s are done publishing.
+  pub async fn run(mut self) -> Result<()> {
     let timeout_duration = Duration::from_secs(self.timeout);
 
+    // no node publishes these fluents, so their subscribers need not wait
+    let publishers = &self.publishers;
+    self.fluents
+        .retain(|fluent_name, _| publishers.contains_key(fluent_name));
+
     // TODO
     // better timeout message than the default "deadline has elapsed"
-    while let Some(fluent) =
-      time::timeout(timeout_duration, input_rx.recv()).await?
-    {
+    loop {
+      // under backpressure, a fluent waiting for room is held back along with
+      // the input it came from, while all other inputs are read on - a node
+      // catching up may have to publish before it can receive again. progress
+      // made from here on is waited for while any fluents are held back.
+      let lag_report = self.lag_report.clone();
+      let progressed = lag_report.progressed();
+      self.unpark()?;
+      let parked = !self.parked.is_empty();
+      let reading = !self.inputs.is_empty() || !parked;
+
+      let inputs = &mut self.inputs;
+      let keep_alive = self.keep_alive || parked;
+      let next = async move {
+        match keep_alive {
+          true => Ok(inputs.next().await),
+          false => time::timeout(timeout_duration, inputs.next()).await,
+        }
+      };
+      let registrations = &mut self.registrations;
+      let registration = async move {
+        match registrations {
+          Some(registrations) => registrations.recv().await,
+          None => None,
+        }
+      };
+      let (node, fluent) = tokio::select! {
+        next = next, if reading => match next? {
+          Some((node, Some(fluent))) => (node, fluent),
+          Some((node, None)) => {
+            self.finish(node);
+            continue;
+          }
+          // the inputs held back are not done yet
+          None if parked => continue,
+          None => break,
+        },
+        Some(registration) = registration => {
+          let Registration { name, publishes, subscribes_to, reply } =
+            registration;
+          let node_ch = self.connect(name, publishes, subscribes_to);
+          // the requesting side may have given up, in which case the new
+          // input ends right away
+          let _ = reply.send(node_ch);
+          continue;
+        }
+        _ = self.lag_report.failed() => {
+          bail!("a node lagged behind its subscriptions, stopping")
+        }
+        _ = progressed, if parked => continue,
+      };
+
       // info!("received fluent: {:?}", fluent);
       let fluent_name = fluent.name().to_string();
-      // sending on a broadcast channel may return an error Result, which just
-      // means that there are no receivers for this channel. however, it still
-      // takes resources and since CURRENTLY all our receivers are known at
-      // compile time, we can just skip that send entirely.
-      if self.fluents[&fluent_name].receiver_count() == 0 {
-        continue;
+      // a node may publish a fluent it has not declared. this is counted and
+      // the fluent is dropped, unless channels are created for unknown names
+      // as they come up - which nobody is subscribed to, unless a node
+      // registered later on subscribes to them. until then, fluents on these
+      // channels keep being counted.
+      let created = self.created_unknown.contains(&fluent_name);
+      if created || !self.fluents.contains_key(&fluent_name) {
+        // unwrap here is safe: locking `Mutex` cannot fail
+        let mut unknown_fluents = self.unknown_fluents.lock().unwrap();
+        let count = unknown_fluents.entry(fluent_name.clone()).or_default();
+        if *count == 0 {
+          warn!("received fluent '{}', which no node was registered with",
+                fluent_name);
+        }
+        *count += 1;
+
+        if !self.create_unknown {
+          continue;
+        }
+        if !created {
+          let (tx, _) = broadcast::channel(self.broadcast_capacity);
+          self.fluents.insert(fluent_name.clone(), tx);
+          self.created_unknown.insert(fluent_name.clone());
+        }
       }
 
-      // now we can safely ? on the send; otherwise we could use a match here.
-      self.fluents[&fluent_name].send(fluent)?;
+      // with backpressure, the slowest subscriber is waited for rather than
+      // have it miss the oldest fluent in the channel
+      if let Some(fluent) = self.forward(fluent)? {
+        // unwrap here is safe: the fluent was just read from this input
+        let input = self.inputs.remove(&node).unwrap();
+        self.parked.insert(node, (fluent, input));
+      }
     }
     Ok(())
   }
@@ -87,10 +361,24 @@ impl Broker {
 
 #[cfg(test)]
 mod tests {
-  // use super::Broker;
-  // use crate::fluent::Fluent;
+  use super::Broker;
+  use crate::{app_core::{testing::{self, TestNode},
+                         Database},
+              fluent::{Fluent, FluentTrait, Key, ValueType},
+              handler::{Alignment,
+                        Emit,
+                        EvalFn,
+                        Handler,
+                        HandlerDefinition,
+                        KeyDependency,
+                        Lateness},
+              stringvec};
 
-  // use pretty_assertions::assert_eq;
+  use futures::future::{self, FutureExt};
+  use indoc::{formatdoc, indoc};
+  use pretty_assertions::assert_eq;
+  use std::{sync::Arc, time::Duration};
+  use tokio::time;
 
 
   #[test]
@@ -101,4 +389,212 @@ mod tests {
     //                                         ["subscription_one",
     //                                          "subscription_two"]);
   }
+
+  fn broker(create_unknown: bool) -> Broker {
+    let config = formatdoc! {"
+      broadcast_capacity = 4
+      timeout = 1
+      create_unknown = {}
+    ", create_unknown};
+    toml::from_str(&config).unwrap()
+  }
+
+  fn fluent(timestamp: usize) -> Fluent {
+    Fluent::new("speed", &[Key::from(1)], timestamp, Box::new(1.0))
+  }
+
+  #[tokio::test]
+  async fn drain_test() {
+    let mut broker = broker(false);
+    let mut publisher = TestNode { publishes: stringvec!["speed"],
+                                   ..Default::default() };
+    let mut subscriber = TestNode { subscribes_to: stringvec!["speed"],
+                                    ..Default::default() };
+    broker.register(&mut publisher);
+    broker.register(&mut subscriber);
+    let runner = tokio::spawn(broker.run());
+
+    let (node_tx, _) = publisher.node_ch.unwrap();
+    for timestamp in 0..3 {
+      node_tx.send(fluent(timestamp)).await.unwrap();
+    }
+    drop(node_tx);
+
+    // once the publisher is done, the subscriber gets what was published and
+    // then the end of its subscription, and the broker stops
+    let (_, mut node_rx) = subscriber.node_ch.unwrap();
+    for timestamp in 0..3 {
+      let (_, fluent) = node_rx.recv().await.unwrap().unwrap();
+      assert_eq!(fluent.timestamp(), timestamp);
+    }
+    assert!(node_rx.recv().await.unwrap().is_none());
+    assert!(runner.await.unwrap().is_ok());
+  }
+
+  #[tokio::test]
+  async fn registrar_test() {
+    let mut broker = broker(false);
+    let mut publisher = TestNode { publishes: stringvec!["speed"],
+                                   ..Default::default() };
+    broker.register(&mut publisher);
+    let registrar = broker.registrar();
+    let runner = tokio::spawn(broker.run());
+
+    // a node registered while the broker is running receives what is
+    // published from then on
+    let mut subscriber = TestNode { subscribes_to: stringvec!["speed"],
+                                    ..Default::default() };
+    registrar.register(&mut subscriber).await.unwrap();
+
+    let (node_tx, _) = publisher.node_ch.unwrap();
+    node_tx.send(fluent(0)).await.unwrap();
+    drop(node_tx);
+
+    let (_, mut node_rx) = subscriber.node_ch.unwrap();
+    assert_eq!(node_rx.recv().await.unwrap().unwrap().1.timestamp(), 0);
+    assert!(node_rx.recv().await.unwrap().is_none());
+    assert!(runner.await.unwrap().is_ok());
+
+    // once the broker has stopped, registering fails
+    let mut late = TestNode::default();
+    assert!(registrar.register(&mut late).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn backpressure_test() {
+    let mut broker: Broker = toml::from_str(indoc! {r#"
+      broadcast_capacity = 4
+      timeout = 1
+      lag_policy = "backpressure"
+    "#}).unwrap();
+    let mut publisher = TestNode { publishes: stringvec!["speed"],
+                                   ..Default::default() };
+    let mut subscriber = TestNode { subscribes_to: stringvec!["speed"],
+                                    ..Default::default() };
+    let mut dropped = TestNode { subscribes_to: stringvec!["speed"],
+                                 ..Default::default() };
+    broker.register(&mut publisher);
+    broker.register(&mut subscriber);
+    broker.register(&mut dropped);
+    let lag_report = broker.lag_report();
+    let runner = tokio::spawn(broker.run());
+
+    let (node_tx, _) = publisher.node_ch.unwrap();
+    for timestamp in 0..10 {
+      node_tx.send(fluent(timestamp)).await.unwrap();
+    }
+    drop(node_tx);
+
+    // the broker waits for the subscriber to make room, and does not wait
+    // for one which has stopped receiving altogether
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!runner.is_finished());
+    drop(dropped);
+    let (_, mut node_rx) = subscriber.node_ch.unwrap();
+    for timestamp in 0..10 {
+      let (_, fluent) = node_rx.recv().await.unwrap().unwrap();
+      assert_eq!(fluent.timestamp(), timestamp);
+    }
+    assert!(node_rx.recv().await.unwrap().is_none());
+    assert!(runner.await.unwrap().is_ok());
+    assert!(lag_report.skipped().is_empty());
+  }
+
+  async fn pairs(input_capacity: Option<usize>) -> usize {
+    let mut config = indoc! {r#"
+      broadcast_capacity = 4
+      timeout = 1
+      lag_policy = "backpressure"
+    "#}.to_owned();
+    if let Some(capacity) = input_capacity {
+      config.push_str(&format!("input_capacity = {}", capacity));
+    }
+    let mut broker: Broker = toml::from_str(&config).unwrap();
+
+    let eval_fn = EvalFn::specify(Arc::new(|_: Vec<Fluent>, _: Database| {
+                                    future::ready(Some(Box::new(true) as Box<dyn ValueType>)).boxed()
+                                  }));
+    let key_dependency = KeyDependency::NonConcurrent { timeout: 3_600,
+                                                        pairing: None, };
+    let def = HandlerDefinition { fluent_name: "paired",
+                                  dependencies: &["speed"],
+                                  key_dependency,
+                                  alignment: Alignment::Exact,
+                                  database_query: None,
+                                  emit: Emit::Always,
+                                  eval_fn };
+    let database = testing::database();
+    let mut handler =
+      Handler::new(def, 3_600, Lateness::default(), database).await
+                                                             .unwrap();
+    let mut source = TestNode { publishes: stringvec!["speed"],
+                                ..Default::default() };
+    let mut sink = TestNode { subscribes_to: stringvec!["paired"],
+                              ..Default::default() };
+    broker.register(&mut source);
+    broker.register(&mut handler);
+    broker.register(&mut sink);
+    let lag_report = broker.lag_report();
+    let runner = tokio::spawn(broker.run());
+    let handler = tokio::spawn(handler.run());
+
+    // each key is paired with all keys before it, so the handler publishes
+    // far more than it receives, and more than fits in its queues
+    let (node_tx, _) = source.node_ch.unwrap();
+    tokio::spawn(async move {
+      for key in 0..100 {
+        let fluent = Fluent::new("speed",
+                                 &[Key::from(key)],
+                                 100 + key as usize,
+                                 Box::new(1.0));
+        node_tx.send(fluent).await.unwrap();
+      }
+    });
+
+    let (_, mut node_rx) = sink.node_ch.unwrap();
+    let mut pairs = 0;
+    while node_rx.recv().await.unwrap().is_some() {
+      pairs += 1;
+    }
+    assert!(handler.await.unwrap().is_ok());
+    assert!(runner.await.unwrap().is_ok());
+    assert!(lag_report.skipped().is_empty());
+    pairs
+  }
+
+  #[tokio::test]
+  async fn backpressure_handler_test() {
+    // the broker keeps reading what the handler publishes while it waits for
+    // the handler to catch up, whether publishing waits for it or not
+    for input_capacity in [None, Some(4)] {
+      let pairs =
+        time::timeout(Duration::from_secs(10), pairs(input_capacity));
+      assert_eq!(pairs.await.unwrap(), 100 * 99 / 2);
+    }
+  }
+
+  async fn unknown_fluents(create_unknown: bool) -> Option<u64> {
+    let mut broker = broker(create_unknown);
+    let mut publisher = TestNode::default();
+    broker.register(&mut publisher);
+    let unknown_fluents = broker.unknown_fluents();
+    let runner = tokio::spawn(broker.run());
+
+    let (node_tx, _) = publisher.node_ch.unwrap();
+    for timestamp in 0..3 {
+      node_tx.send(fluent(timestamp)).await.unwrap();
+    }
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!runner.is_finished());
+    runner.abort();
+
+    let count = unknown_fluents.lock().unwrap().get("speed").copied();
+    count
+  }
+
+  #[tokio::test]
+  async fn unknown_fluent_test() {
+    assert_eq!(unknown_fluents(false).await, Some(3));
+    assert_eq!(unknown_fluents(true).await, Some(3));
+  }
 }
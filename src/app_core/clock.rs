@@ -0,0 +1,195 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use crate::fluent::{TimeUnit, Timestamp};
+
+use eyre::{bail, Result};
+use serde::Deserialize;
+use tokio::{task,
+            time::{self, Duration, Instant, Interval}};
+
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+/// Selects how the [`ReplayClock`] paces a replay of recorded data.
+pub enum ClockMode {
+  /// Advances one second of data time per cycle, whether there is data for
+  /// that second or not.
+  #[default]
+  Fixed,
+  /// Advances one second of data time per cycle, jumping ahead to the next
+  /// data point if there is no data for that second.
+  SkipGaps,
+  /// Advances to the next data point without waiting at all.
+  AsFastAsPossible,
+  /// Advances to the next data point at the time it occurred relative to the
+  /// start of the replay, sped up by the given factor.
+  RealTime { speed_up: f64 },
+}
+
+impl ClockMode {
+  /// Checks the parameters of the [`ClockMode`] which deserialize fine but
+  /// cannot be run with, such as a `speed_up` of zero.
+  pub fn validate(&self) -> Result<()> {
+    if let Self::RealTime { speed_up } = self {
+      if !speed_up.is_finite() || *speed_up <= 0.0 {
+        bail!("clock speed_up must be positive and finite, is {}",
+              speed_up);
+      }
+    }
+    Ok(())
+  }
+}
+
+
+#[derive(Debug)]
+/// Paces the replay of recorded data according to its [`ClockMode`], i.e.
+/// decides how data time advances and how long that takes in wall time.
+pub struct ReplayClock {
+  mode:       ClockMode,
+  interval:   Interval,
+  per_second: Timestamp,
+  start_time: Timestamp,
+  started_at: Instant,
+}
+
+impl ReplayClock {
+  /// Sets up a [`ReplayClock`] for a replay beginning at `start_time`, with
+  /// timestamps counted in `time_unit`. The cycle length is used by the modes
+  /// which advance once per cycle.
+  pub fn new(mode: ClockMode,
+             millis_per_cycle: u64,
+             time_unit: TimeUnit,
+             start_time: Timestamp)
+             -> Self {
+    let interval = time::interval(Duration::from_millis(millis_per_cycle));
+    let per_second = time_unit.per_second();
+    let started_at = Instant::now();
+
+    Self { mode,
+           interval,
+           per_second,
+           start_time,
+           started_at }
+  }
+
+  /// Waits as required by the [`ClockMode`] and returns the data time to
+  /// process next, given the current data time and the timestamp of the next
+  /// pending data point.
+  pub async fn advance(&mut self,
+                       time: Timestamp,
+                       next: Timestamp)
+                       -> Timestamp {
+    let next = std::cmp::max(next, time + 1);
+    match self.mode {
+      ClockMode::Fixed => {
+        self.interval.tick().await;
+        time + self.per_second
+      }
+      ClockMode::SkipGaps => {
+        self.interval.tick().await;
+        std::cmp::max(next, time + self.per_second)
+      }
+      ClockMode::AsFastAsPossible => {
+        // let the rest of the application catch up without actually waiting
+        task::yield_now().await;
+        next
+      }
+      ClockMode::RealTime { speed_up } => {
+        let offset = (next - self.start_time) as f64
+                     / (self.per_second as f64 * speed_up);
+        time::sleep_until(self.started_at + Duration::from_secs_f64(offset))
+          .await;
+        next
+      }
+    }
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{ClockMode, ReplayClock};
+  use crate::fluent::TimeUnit;
+
+  use pretty_assertions::assert_eq;
+  use tokio::time::{Duration, Instant};
+
+
+  #[tokio::test]
+  async fn fixed_clock_test() {
+    let mut clock =
+      ReplayClock::new(ClockMode::Fixed, 1, TimeUnit::Seconds, 1000);
+
+    assert_eq!(clock.advance(1000, 1005).await, 1001);
+    assert_eq!(clock.advance(1001, 1005).await, 1002);
+
+    let mut clock =
+      ReplayClock::new(ClockMode::Fixed, 1, TimeUnit::Milliseconds, 1000);
+
+    assert_eq!(clock.advance(1000, 1005).await, 2000);
+    assert_eq!(clock.advance(2000, 9000).await, 3000);
+  }
+
+  #[tokio::test]
+  async fn skip_gaps_clock_test() {
+    let mut clock =
+      ReplayClock::new(ClockMode::SkipGaps, 1, TimeUnit::Seconds, 1000);
+
+    assert_eq!(clock.advance(1000, 1005).await, 1005);
+    assert_eq!(clock.advance(1005, 1005).await, 1006);
+
+    let mut clock =
+      ReplayClock::new(ClockMode::SkipGaps, 1, TimeUnit::Milliseconds, 1000);
+
+    assert_eq!(clock.advance(1000, 1005).await, 2000);
+    assert_eq!(clock.advance(2000, 9000).await, 9000);
+  }
+
+  #[tokio::test]
+  async fn as_fast_as_possible_clock_test() {
+    let mut clock = ReplayClock::new(ClockMode::AsFastAsPossible,
+                                     10_000,
+                                     TimeUnit::Seconds,
+                                     0);
+
+    let started_at = Instant::now();
+    assert_eq!(clock.advance(0, 3_600).await, 3_600);
+    assert_eq!(clock.advance(3_600, 7_200).await, 7_200);
+    assert!(started_at.elapsed() < Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn real_time_clock_test() {
+    let mode = ClockMode::RealTime { speed_up: 100.0 };
+    let mut clock = ReplayClock::new(mode, 10_000, TimeUnit::Seconds, 1000);
+
+    let started_at = Instant::now();
+    assert_eq!(clock.advance(1000, 1005).await, 1005);
+    assert!(started_at.elapsed() >= Duration::from_millis(50));
+    assert!(started_at.elapsed() < Duration::from_secs(1));
+
+    let mode = ClockMode::RealTime { speed_up: 0.5 };
+    let mut clock =
+      ReplayClock::new(mode, 10_000, TimeUnit::Microseconds, 1000);
+
+    let started_at = Instant::now();
+    assert_eq!(clock.advance(1000, 26_000).await, 26_000);
+    assert!(started_at.elapsed() >= Duration::from_millis(50));
+    assert!(started_at.elapsed() < Duration::from_secs(1));
+  }
+
+  #[test]
+  fn clock_mode_test() {
+    let mode: ClockMode = toml::from_str(r#"mode = "skip_gaps""#).unwrap();
+    assert_eq!(mode, ClockMode::SkipGaps);
+
+    let mode: ClockMode =
+      toml::from_str("mode = \"real_time\"\nspeed_up = 10.0").unwrap();
+    assert_eq!(mode, ClockMode::RealTime { speed_up: 10.0 });
+  }
+}
@@ -55,6 +55,11 @@ impl Database {
     self
   }
 
+  /// Returns whether a query template is set for this instance.
+  pub fn has_template(&self) -> bool {
+    !self.template.is_empty()
+  }
+
   /// Query the database using the statement template. Queries must return
   /// exactly one value in exactly one row for this to return `Ok(T)`.
   pub async fn query<T>(&self,
@@ -0,0 +1,482 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::node::Node;
+use crate::handler::{side_channel, Alignment, Handler, KeyDependency};
+
+use clap::ValueEnum;
+use eyre::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Role of a [`Node`] in the [`FluentGraph`].
+pub enum NodeKind {
+  Source,
+  Handler,
+  Sink,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+/// Formats a [`FluentGraph`] can be exported in.
+pub enum GraphFormat {
+  /// Graphviz DOT
+  Dot,
+  /// Mermaid flowchart
+  Mermaid,
+}
+
+
+#[derive(Clone, Debug)]
+/// What the [`FluentGraph`] knows about a [`Node`].
+struct GraphNode {
+  kind:          NodeKind,
+  name:          String,
+  publishes:     Vec<String>,
+  subscribes_to: Vec<String>,
+  annotations:   Vec<String>,
+}
+
+
+#[derive(Clone, Debug, Default)]
+/// Publish/subscribe graph of all [`Node`]s of a run, built before any of
+/// them is registered at the [`Broker`](super::broker::Broker) to check that
+/// the fluents fit together.
+pub struct FluentGraph {
+  nodes:            Vec<GraphNode>,
+  allow_unconsumed: bool,
+}
+
+impl FluentGraph {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Lets handlers publish fluents no node subscribes to, which are then only
+  /// warned about, see [`FluentGraph::unconsumed`].
+  pub fn allow_unconsumed(&mut self) {
+    self.allow_unconsumed = true;
+  }
+
+  /// Adds a [`Node`] with the fluents it publishes and subscribes to.
+  pub fn add<T: Node + ?Sized>(&mut self, kind: NodeKind, node: &T) {
+    self.add_annotated(kind, node, Vec::new());
+  }
+
+  /// Adds a [`Node`] like [`FluentGraph::add`], with annotations shown
+  /// alongside its name when the graph is rendered.
+  pub fn add_annotated<T: Node + ?Sized>(&mut self,
+                                         kind: NodeKind,
+                                         node: &T,
+                                         annotations: Vec<String>) {
+    self.nodes.push(GraphNode { kind,
+                                name: node.name(),
+                                publishes: node.publishes(),
+                                subscribes_to: node.subscribes_to(),
+                                annotations });
+  }
+
+  /// Adds a [`Handler`], annotated with its key dependency and whether it
+  /// queries the database.
+  pub fn add_handler(&mut self, handler: &Handler) {
+    let mut annotations = vec![handler.key_dependency().to_string()];
+    if handler.alignment() != Alignment::Exact
+       && !matches!(handler.key_dependency(),
+                    KeyDependency::NonConcurrent { .. })
+    {
+      annotations.push(handler.alignment().to_string());
+    }
+    if handler.has_database_query() {
+      annotations.push("database query".to_owned());
+    }
+    self.add_annotated(NodeKind::Handler, handler, annotations);
+  }
+
+  /// Removes the [`Handler`] of the given name, e.g. to check the graph
+  /// without it before it is retired.
+  pub fn remove_handler(&mut self, name: &str) {
+    self.nodes
+        .retain(|node| node.kind != NodeKind::Handler || node.name != name);
+  }
+
+  /// Checks the graph, failing with a list of all problems found:
+  /// subscriptions to fluents no node publishes, fluents published by more
+  /// than one node, handler outputs no node subscribes to (unless allowed)
+  /// and cycles of handlers depending on each other.
+  pub fn validate(&self) -> Result<()> {
+    let mut problems = Vec::new();
+
+    let publishers = self.publishers();
+    for (fluent_name, nodes) in publishers.iter() {
+      if nodes.len() > 1 {
+        problems.push(format!("'{}' is published by more than one node: {}",
+                              fluent_name,
+                              quoted(nodes)));
+      }
+    }
+
+    for node in self.nodes.iter() {
+      for fluent_name in node.subscribes_to.iter() {
+        if !publishers.contains_key(fluent_name) {
+          problems.push(format!("'{}' subscribes to '{}', which no node \
+                                 publishes",
+                                node.name, fluent_name));
+        }
+      }
+    }
+
+    for (fluent_name, node_name) in self.unconsumed() {
+      let problem = format!("'{}' is published by handler '{}', but no node \
+                             subscribes to it",
+                            fluent_name, node_name);
+      match self.allow_unconsumed {
+        true => warn!("{}", problem),
+        false => problems.push(problem),
+      }
+    }
+
+    for cycle in self.cycles() {
+      problems.push(format!("fluents depend on each other in a cycle: {}",
+                            cycle.join(" -> ")));
+    }
+
+    if !problems.is_empty() {
+      bail!("invalid fluent graph:\n  - {}", problems.join("\n  - "));
+    }
+    Ok(())
+  }
+
+  /// Returns the fluents published by handlers which no node subscribes to,
+  /// along with the handler publishing them, as these are evaluated in vain.
+  /// The side channel of late fluents is left out, being only of interest at
+  /// times.
+  pub fn unconsumed(&self) -> Vec<(String, String)> {
+    let consumed = self.nodes
+                       .iter()
+                       .flat_map(|node| node.subscribes_to.iter())
+                       .collect::<BTreeSet<_>>();
+    self.nodes
+        .iter()
+        .filter(|node| node.kind == NodeKind::Handler)
+        .flat_map(|node| {
+          node.publishes
+              .iter()
+              .filter(|fluent_name| {
+                !consumed.contains(fluent_name)
+                && **fluent_name != side_channel(&node.name)
+              })
+              .map(|fluent_name| (fluent_name.clone(), node.name.clone()))
+        })
+        .collect()
+  }
+
+  /// Renders the graph in the given format, with one vertex per [`Node`] and
+  /// one edge per fluent passed from one [`Node`] to another. Edges are
+  /// labelled with the fluent, unless it is named like its publisher.
+  pub fn render(&self, format: GraphFormat) -> String {
+    let mut lines = Vec::new();
+    match format {
+      GraphFormat::Dot => {
+        lines.push("digraph fluents {".to_owned());
+        lines.push("  rankdir=LR;".to_owned());
+        for (i, node) in self.nodes.iter().enumerate() {
+          let shape = match node.kind {
+            NodeKind::Source => "invhouse",
+            NodeKind::Handler => "box",
+            NodeKind::Sink => "cylinder",
+          };
+          lines.push(format!("  n{} [label=\"{}\", shape={}];",
+                             i,
+                             escaped(&node.label("\\n")),
+                             shape));
+        }
+        for (from, to, fluent_name) in self.edges() {
+          lines.push(match self.nodes[from].name == *fluent_name {
+                       true => format!("  n{} -> n{};", from, to),
+                       false => format!("  n{} -> n{} [label=\"{}\"];",
+                                        from,
+                                        to,
+                                        escaped(fluent_name)),
+                     });
+        }
+        lines.push("}".to_owned());
+      }
+      GraphFormat::Mermaid => {
+        lines.push("flowchart LR".to_owned());
+        for (i, node) in self.nodes.iter().enumerate() {
+          let (open, close) = match node.kind {
+            NodeKind::Source => ("([", "])"),
+            NodeKind::Handler => ("[", "]"),
+            NodeKind::Sink => ("[(", ")]"),
+          };
+          lines.push(format!("  n{}{}\"{}\"{}",
+                             i,
+                             open,
+                             node.label("<br/>").replace('"', "#quot;"),
+                             close));
+        }
+        for (from, to, fluent_name) in self.edges() {
+          lines.push(match self.nodes[from].name == *fluent_name {
+                       true => format!("  n{} --> n{}", from, to),
+                       false => {
+                         format!("  n{} -->|{}| n{}", from, fluent_name, to)
+                       }
+                     });
+        }
+      }
+    }
+    lines.join("\n")
+  }
+
+  /// Returns the edges of the graph as indices of the publishing and the
+  /// subscribing node, along with the fluent passed between them.
+  fn edges(&self) -> Vec<(usize, usize, &String)> {
+    let mut edges = Vec::new();
+    for (to, node) in self.nodes.iter().enumerate() {
+      for fluent_name in node.subscribes_to.iter() {
+        for (from, publisher) in self.nodes.iter().enumerate() {
+          if publisher.publishes.contains(fluent_name) {
+            edges.push((from, to, fluent_name));
+          }
+        }
+      }
+    }
+    edges
+  }
+
+  /// Returns the names of the nodes publishing each fluent.
+  fn publishers(&self) -> BTreeMap<&String, Vec<&String>> {
+    let mut publishers = BTreeMap::<_, Vec<_>>::new();
+    for node in self.nodes.iter() {
+      for fluent_name in node.publishes.iter() {
+        publishers.entry(fluent_name).or_default().push(&node.name);
+      }
+    }
+    publishers
+  }
+
+  /// Returns the cycles among fluents, each starting and ending with the same
+  /// fluent. A fluent depends on the fluents its publisher subscribes to.
+  fn cycles(&self) -> Vec<Vec<String>> {
+    let mut dependents = BTreeMap::<_, BTreeSet<_>>::new();
+    for node in self.nodes.iter() {
+      for dependency in node.subscribes_to.iter() {
+        dependents.entry(dependency.as_str())
+                  .or_default()
+                  .extend(node.publishes.iter().map(|f| f.as_str()));
+      }
+    }
+
+    let mut cycles = Vec::new();
+    let mut done = BTreeSet::new();
+    for &start in dependents.keys() {
+      let mut path = Vec::new();
+      find_cycles(start, &dependents, &mut path, &mut done, &mut cycles);
+    }
+    cycles
+  }
+}
+
+impl GraphNode {
+  /// Name of the node followed by its annotations, if any, on a new line.
+  fn label(&self, line_break: &str) -> String {
+    match self.annotations.is_empty() {
+      true => self.name.clone(),
+      false => {
+        format!("{}{}{}", self.name, line_break, self.annotations.join(", "))
+      }
+    }
+  }
+}
+
+
+/// Depth-first search for cycles from the given fluent, keeping the fluents
+/// on the current path and those whose dependents have all been searched.
+fn find_cycles<'a>(fluent_name: &'a str,
+                   dependents: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+                   path: &mut Vec<&'a str>,
+                   done: &mut BTreeSet<&'a str>,
+                   cycles: &mut Vec<Vec<String>>) {
+  if done.contains(fluent_name) {
+    return;
+  }
+  if let Some(position) = path.iter().position(|&f| f == fluent_name) {
+    let mut cycle = path[position..].iter()
+                                    .map(|f| f.to_string())
+                                    .collect::<Vec<_>>();
+    cycle.push(fluent_name.to_owned());
+    cycles.push(cycle);
+    return;
+  }
+
+  path.push(fluent_name);
+  for &dependent in dependents.get(fluent_name).into_iter().flatten() {
+    find_cycles(dependent, dependents, path, done, cycles);
+  }
+  path.pop();
+  done.insert(fluent_name);
+}
+
+
+/// Escapes a string for use in a quoted DOT identifier.
+fn escaped(s: &str) -> String {
+  s.replace('"', "\\\"")
+}
+
+
+fn quoted(names: &[&String]) -> String {
+  names.iter()
+       .map(|n| format!("'{}'", n))
+       .collect::<Vec<_>>()
+       .join(", ")
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{FluentGraph, GraphFormat, NodeKind};
+  use crate::{app_core::testing::TestNode, stringvec};
+
+  use indoc::indoc;
+  use pretty_assertions::assert_eq;
+
+
+  fn graph(handlers: &[(&'static str,
+              &'static [&'static str],
+              &'static [&'static str])],
+           sink_subscribes_to: &'static [&'static str])
+           -> FluentGraph {
+    let mut graph = FluentGraph::new();
+    graph.add(NodeKind::Source,
+              &TestNode::new("source", &["lon", "lat", "speed"], &[]));
+    for &(name, publishes, subscribes_to) in handlers {
+      graph.add(NodeKind::Handler,
+                &TestNode::new(name, publishes, subscribes_to));
+    }
+    graph.add(NodeKind::Sink,
+              &TestNode::new("sink", &[], sink_subscribes_to));
+    graph
+  }
+
+  #[test]
+  fn valid_graph_test() {
+    let graph = graph(&[("location", &["location"], &["lon", "lat"]),
+                        ("high_speed", &["high_speed"], &["speed"])],
+                      &["location", "high_speed"]);
+    assert!(graph.validate().is_ok());
+  }
+
+  #[test]
+  fn unconsumed_test() {
+    // late fluents published to a side channel need not be recorded
+    let late =
+      graph(&[("high_speed", &["high_speed", "high_speed_late"], &["speed"])],
+            &["high_speed"]);
+    assert!(late.validate().is_ok());
+    assert_eq!(late.unconsumed(), Vec::new());
+
+    let mut unused = graph(&[("location", &["location"], &["lon", "lat"]),
+                             ("unused", &["unused"], &["speed"])],
+                           &["location"]);
+    assert_eq!(unused.validate().unwrap_err().to_string(),
+               "invalid fluent graph:\n  - 'unused' is published by handler \
+                'unused', but no node subscribes to it");
+    unused.allow_unconsumed();
+    assert!(unused.validate().is_ok());
+  }
+
+  #[test]
+  fn invalid_graph_test() {
+    let graph = graph(&[("location", &["location"], &["lon", "lat"]),
+                        ("high_speed", &["high_speed"], &["sped"]),
+                        ("high_speed_2", &["high_speed"], &["speed"]),
+                        ("near_coast", &["near_coast"], &["distance"]),
+                        ("distance", &["distance"], &["near_coast"]),
+                        ("unused", &["unused"], &["speed"])],
+                      &["location", "high_speed", "near_coast"]);
+    let err = graph.validate().unwrap_err();
+    assert_eq!(graph.unconsumed(),
+               vec![("unused".to_owned(), "unused".to_owned())]);
+
+    assert_eq!(err.to_string().lines().collect::<Vec<_>>(),
+               vec!["invalid fluent graph:",
+                    "  - 'high_speed' is published by more than one node: \
+                     'high_speed', 'high_speed_2'",
+                    "  - 'high_speed' subscribes to 'sped', which no node \
+                     publishes",
+                    "  - 'unused' is published by handler 'unused', but no \
+                     node subscribes to it",
+                    "  - fluents depend on each other in a cycle: distance \
+                     -> near_coast -> distance"]);
+  }
+
+  #[test]
+  fn remove_handler_test() {
+    let mut graph = graph(&[("location", &["location"], &["lon", "lat"]),
+                            ("high_speed", &["high_speed"], &["speed"])],
+                          &["location", "high_speed"]);
+    graph.remove_handler("high_speed");
+    assert!(graph.validate().is_err());
+
+    graph.add(NodeKind::Handler,
+              &TestNode::new("high_speed", &["high_speed"], &["lon"]));
+    assert!(graph.validate().is_ok());
+  }
+
+  #[test]
+  fn cycles_test() {
+    let graph = graph(&[("a", &["a"], &["speed", "c"]),
+                        ("b", &["b"], &["a"]),
+                        ("c", &["c"], &["b"]),
+                        ("d", &["d"], &["d"])],
+                      &["a"]);
+    assert_eq!(graph.cycles(),
+               vec![stringvec!["a", "b", "c", "a"], stringvec!["d", "d"]]);
+  }
+
+  fn render_graph() -> FluentGraph {
+    let mut graph = graph(&[], &["lon", "location"]);
+    graph.add_annotated(NodeKind::Handler,
+                        &TestNode::new("location",
+                                       &["location"],
+                                       &["lon", "lat"]),
+                        stringvec!["static", "database query"]);
+    graph
+  }
+
+  #[test]
+  fn dot_test() {
+    assert_eq!(render_graph().render(GraphFormat::Dot),
+               indoc! {r#"
+                 digraph fluents {
+                   rankdir=LR;
+                   n0 [label="source", shape=invhouse];
+                   n1 [label="sink", shape=cylinder];
+                   n2 [label="location\nstatic, database query", shape=box];
+                   n0 -> n1 [label="lon"];
+                   n2 -> n1;
+                   n0 -> n2 [label="lon"];
+                   n0 -> n2 [label="lat"];
+                 }"#});
+  }
+
+  #[test]
+  fn mermaid_test() {
+    assert_eq!(render_graph().render(GraphFormat::Mermaid),
+               indoc! {r#"
+                 flowchart LR
+                   n0(["source"])
+                   n1[("sink")]
+                   n2["location<br/>static, database query"]
+                   n0 -->|lon| n1
+                   n2 --> n1
+                   n0 -->|lon| n2
+                   n0 -->|lat| n2"#});
+  }
+}
@@ -0,0 +1,210 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::sink::CopyRow;
+use crate::{fluent::{Fluent, FluentTrait, Key, Timestamp},
+            sqlvec};
+
+use std::collections::HashMap;
+use tokio_postgres::types::{ToSql, Type};
+use tracing::debug;
+
+
+#[derive(Debug, PartialEq)]
+/// A row of the intervals table: a maximal interval `[start_time, end_time)`
+/// during which a boolean fluent held for the given keys. Intervals still open
+/// when the run ends have no `end_time`.
+pub struct IntervalRow {
+  name:       String,
+  keys:       Vec<String>,
+  start_time: i64,
+  end_time:   Option<i64>,
+}
+
+impl CopyRow for IntervalRow {
+  fn statement() -> &'static str {
+    include_str!("./sql/intervals.sql")
+  }
+
+  fn types() -> Vec<Type> {
+    vec![Type::TEXT, Type::TEXT_ARRAY, Type::INT8, Type::INT8]
+  }
+
+  fn args(&self) -> Vec<&(dyn ToSql + Sync)> {
+    sqlvec![&self.name, &self.keys, &self.start_time, &self.end_time]
+  }
+}
+
+
+#[derive(Debug)]
+/// Interval of a fluent which is still holding.
+struct OpenInterval {
+  start_time: Timestamp,
+  last_seen:  Timestamp,
+}
+
+
+#[derive(Debug)]
+/// Folds consecutive samples of boolean fluents into the maximal intervals
+/// during which they held, per fluent name and keys. An interval is closed
+/// when the fluent no longer holds, ending at that sample, or when no sample
+/// has been seen for longer than the timeout, ending one unit after the last
+/// sample seen.
+pub struct Intervals {
+  timeout: Option<Timestamp>,
+  now:     Timestamp,
+  open:    HashMap<(String, Vec<Key>), OpenInterval>,
+}
+
+impl Intervals {
+  /// Sets up [`Intervals`] without any open intervals. The timeout is given
+  /// in the timestamp unit of the source; without it, intervals are only
+  /// closed by samples in which the fluent does not hold.
+  pub fn new(timeout: Option<Timestamp>) -> Self {
+    Self { timeout,
+           now: 0,
+           open: HashMap::new() }
+  }
+
+  /// Folds a sample into the open intervals, returning those closed by it.
+  /// Fluents which are not boolean are skipped.
+  pub fn fold(&mut self, fluent: &Fluent) -> Vec<IntervalRow> {
+    if !matches!(fluent, Fluent::Boolean(_)) {
+      debug!("skipping non-boolean fluent '{}'", fluent.name());
+      return Vec::new();
+    }
+
+    let timestamp = fluent.timestamp();
+    let mut closed = self.close_timed_out(timestamp);
+
+    let id = (fluent.name().to_owned(), fluent.keys().to_vec());
+    match (fluent.value::<bool>(), self.open.get_mut(&id)) {
+      (true, Some(interval)) => interval.last_seen = timestamp,
+      (true, None) => {
+        self.open.insert(id,
+                         OpenInterval { start_time: timestamp,
+                                        last_seen:  timestamp, });
+      }
+      (false, Some(_)) => {
+        // unwrap here is safe: we've just found the interval
+        let interval = self.open.remove(&id).unwrap();
+        closed.push(row(id, interval.start_time, Some(timestamp)));
+      }
+      (false, None) => (),
+    }
+
+    closed
+  }
+
+  /// Returns all intervals still open, without an end.
+  pub fn close_all(&mut self) -> Vec<IntervalRow> {
+    self.open
+        .drain()
+        .map(|(id, interval)| row(id, interval.start_time, None))
+        .collect()
+  }
+
+  /// Closes the intervals which have not seen a sample for longer than the
+  /// timeout. Checked only once data time has advanced.
+  fn close_timed_out(&mut self, timestamp: Timestamp) -> Vec<IntervalRow> {
+    let timeout = match self.timeout {
+      Some(timeout) if timestamp > self.now => timeout,
+      _ => return Vec::new(),
+    };
+    self.now = timestamp;
+
+    let timed_out = self.open
+                        .iter()
+                        .filter(|(_, i)| i.last_seen + timeout < timestamp)
+                        .map(|(id, _)| id.clone())
+                        .collect::<Vec<_>>();
+
+    timed_out.into_iter()
+             .map(|id| {
+               // unwrap here is safe: we've just found the interval
+               let interval = self.open.remove(&id).unwrap();
+               row(id, interval.start_time, Some(interval.last_seen + 1))
+             })
+             .collect()
+  }
+}
+
+
+fn row((name, keys): (String, Vec<Key>),
+       start_time: Timestamp,
+       end_time: Option<Timestamp>)
+       -> IntervalRow {
+  IntervalRow { name,
+                keys: keys.iter().map(|k| k.to_string()).collect(),
+                start_time: start_time as i64,
+                end_time: end_time.map(|t| t as i64) }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{CopyRow, IntervalRow, Intervals};
+  use crate::{fluent::{Fluent, Key, Timestamp},
+              stringvec};
+
+  use pretty_assertions::assert_eq;
+
+
+  fn sample(key: i64, timestamp: Timestamp, value: bool) -> Fluent {
+    Fluent::new("near_coast", &[Key::from(key)], timestamp, Box::new(value))
+  }
+
+  fn interval(key: &str,
+              start_time: i64,
+              end_time: Option<i64>)
+              -> IntervalRow {
+    IntervalRow { name: String::from("near_coast"),
+                  keys: vec![key.to_owned()],
+                  start_time,
+                  end_time }
+  }
+
+  #[test]
+  fn value_change_test() {
+    let mut intervals = Intervals::new(None);
+
+    assert_eq!(intervals.fold(&sample(1, 10, false)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 11, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(2, 11, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 12, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 20, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 21, false)),
+               vec![interval("1", 11, Some(21))]);
+    assert_eq!(intervals.fold(&sample(1, 22, false)), vec![]);
+    assert_eq!(intervals.close_all(), vec![interval("2", 11, None)]);
+    assert_eq!(intervals.close_all(), vec![]);
+
+    let other = Fluent::new("distance", &[Key::from(1)], 23, Box::new(4.2));
+    assert_eq!(intervals.fold(&other), vec![]);
+  }
+
+  #[test]
+  fn timeout_test() {
+    let mut intervals = Intervals::new(Some(5));
+
+    assert_eq!(intervals.fold(&sample(1, 10, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(2, 12, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 15, true)), vec![]);
+    assert_eq!(intervals.fold(&sample(1, 18, true)),
+               vec![interval("2", 12, Some(13))]);
+    assert_eq!(intervals.fold(&sample(1, 30, true)),
+               vec![interval("1", 10, Some(19))]);
+    assert_eq!(intervals.close_all(), vec![interval("1", 30, None)]);
+  }
+
+  #[test]
+  fn interval_row_test() {
+    let row = interval("1", 10, None);
+    assert_eq!(row.keys, stringvec!["1"]);
+    assert_eq!(row.args().len(), IntervalRow::types().len());
+  }
+}
@@ -14,10 +14,17 @@
 
 mod app_core;
 mod broker;
+mod clock;
 mod database;
+mod graph;
+mod intervals;
 mod node;
+mod output_file;
+mod reload;
 mod sink;
 mod source;
+#[cfg(test)]
+pub mod testing;
 pub mod util;
 
 pub use app_core::AppCore;
@@ -6,23 +6,200 @@
 
 use crate::fluent::Fluent;
 
-use std::fmt;
-use tokio::sync::mpsc;
-use tokio_stream::{wrappers::BroadcastStream, StreamMap};
+use eyre::{bail, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap,
+          fmt,
+          sync::{Arc, Mutex}};
+use tokio::sync::{futures::Notified,
+                  mpsc::{self, error::SendError},
+                  Notify};
+use tokio_stream::{wrappers::{errors::BroadcastStreamRecvError,
+                              BroadcastStream},
+                   StreamExt,
+                   StreamMap};
+use tracing::warn;
 
 
-/// Helper type for [`Node`] senders. All [`Node`]s publish [`Fluent`]s to
-/// the [`Broker`](super::broker::Broker) using this kind of sender.
-pub type NodeTx = mpsc::UnboundedSender<Fluent>;
+#[derive(Clone, Debug)]
+/// Sender for [`Node`]s. All [`Node`]s publish [`Fluent`]s to the
+/// [`Broker`](super::broker::Broker) using this kind of sender. If the
+/// [`Broker`](super::broker::Broker) has a bounded input, sending waits for
+/// it to make room, which throttles the [`Node`].
+pub enum NodeTx {
+  Unbounded(mpsc::UnboundedSender<Fluent>),
+  Bounded(mpsc::Sender<Fluent>),
+}
+
+impl NodeTx {
+  /// Sends a [`Fluent`] to the [`Broker`](super::broker::Broker), failing
+  /// only if it has stopped.
+  pub async fn send(&self, fluent: Fluent) -> Result<(), SendError<Fluent>> {
+    match self {
+      Self::Unbounded(tx) => tx.send(fluent),
+      Self::Bounded(tx) => tx.send(fluent).await,
+    }
+  }
+}
+
+impl From<mpsc::UnboundedSender<Fluent>> for NodeTx {
+  fn from(tx: mpsc::UnboundedSender<Fluent>) -> Self {
+    Self::Unbounded(tx)
+  }
+}
+
+impl From<mpsc::Sender<Fluent>> for NodeTx {
+  fn from(tx: mpsc::Sender<Fluent>) -> Self {
+    Self::Bounded(tx)
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Decides what happens when a [`Node`] falls so far behind on a subscription
+/// that the broadcast channel overflows.
+pub enum LagPolicy {
+  /// The [`Node`] skips the fluents it missed, which are counted and
+  /// reported.
+  #[default]
+  Report,
+  /// The run is stopped.
+  Fail,
+  /// The [`Broker`](super::broker::Broker) waits for the slowest subscriber
+  /// of a fluent to make room before sending it, so no fluents are missed.
+  /// Meanwhile, it holds back whatever the [`Node`] the fluent came from
+  /// publishes after it, and forwards the fluents of all other [`Node`]s.
+  Backpressure,
+}
+
+
+#[derive(Clone, Debug, Default)]
+/// Shared record of the fluents [`Node`]s missed because they lagged behind,
+/// per node and subscription. Also lets the
+/// [`Broker`](super::broker::Broker) wait for [`Node`]s to catch up under
+/// [`LagPolicy::Backpressure`].
+pub struct LagReport {
+  skipped:  Arc<Mutex<BTreeMap<(String, String), u64>>>,
+  failed:   Arc<Notify>,
+  progress: Arc<Notify>,
+}
+
+impl LagReport {
+  /// Returns the number of fluents missed, by node and fluent name.
+  pub fn skipped(&self) -> BTreeMap<(String, String), u64> {
+    // unwrap here is safe: locking `Mutex` cannot fail
+    self.skipped.lock().unwrap().clone()
+  }
+
+  /// Completes once a [`Node`] has stopped due to [`LagPolicy::Fail`].
+  pub async fn failed(&self) {
+    self.failed.notified().await
+  }
+
+  /// Completes once a [`Node`] under [`LagPolicy::Backpressure`] has received
+  /// a fluent or dropped its [`NodeRx`], i.e. may have made room in a
+  /// channel. Only progress made after calling this is waited for.
+  pub fn progressed(&self) -> Notified<'_> {
+    self.progress.notified()
+  }
+
+  fn record(&self, node_name: &str, fluent_name: &str, skipped: u64) {
+    // unwrap here is safe: locking `Mutex` cannot fail
+    *self.skipped
+         .lock()
+         .unwrap()
+         .entry((node_name.to_owned(), fluent_name.to_owned()))
+         .or_default() += skipped;
+  }
+}
+
+
+#[derive(Debug, Default)]
+/// Receiver for [`Node`]s. Bundles the broadcast streams of all fluents a
+/// [`Node`] is subscribed to, and deals with the [`Node`] lagging behind on
+/// any of them according to the [`LagPolicy`].
+pub struct NodeRx {
+  node_name:  String,
+  streams:    StreamMap<String, BroadcastStream<Fluent>>,
+  lag_policy: LagPolicy,
+  lag_report: LagReport,
+}
+
+impl NodeRx {
+  /// Creates a [`NodeRx`] without any subscriptions.
+  pub fn new() -> Self {
+    Self::default()
+  }
 
-/// Helper type  for [`Node`] receivers. All [`Node`]s receive [`Fluent`]s
-/// they are subscribed to using this kind of receiver.
-pub type NodeRx = StreamMap<String, BroadcastStream<Fluent>>;
+  /// Creates a [`NodeRx`] for the named [`Node`], recording lag in the given
+  /// [`LagReport`].
+  pub fn with_lag(node_name: String,
+                  lag_policy: LagPolicy,
+                  lag_report: LagReport)
+                  -> Self {
+    let mut node_rx = Self::new();
+    node_rx.node_name = node_name;
+    node_rx.lag_policy = lag_policy;
+    node_rx.lag_report = lag_report;
+    node_rx
+  }
+
+  /// Adds the stream of a fluent the [`Node`] is subscribed to.
+  pub fn subscribe(&mut self,
+                   fluent_name: String,
+                   stream: BroadcastStream<Fluent>) {
+    self.streams.insert(fluent_name, stream);
+  }
+
+  /// Receives the next fluent, along with the name of the subscription it was
+  /// received on. Returns `None` once all subscriptions have ended, i.e. the
+  /// [`Broker`](super::broker::Broker) has stopped, and an error if the
+  /// [`Node`] lagged behind under [`LagPolicy::Fail`].
+  pub async fn recv(&mut self) -> Result<Option<(String, Fluent)>> {
+    loop {
+      match self.streams.next().await {
+        Some((fluent_name, Ok(fluent))) => {
+          if self.lag_policy == LagPolicy::Backpressure {
+            self.lag_report.progress.notify_waiters();
+          }
+          return Ok(Some((fluent_name, fluent)));
+        }
+        Some((fluent_name,
+              Err(BroadcastStreamRecvError::Lagged(skipped)))) => {
+          warn!("'{}' lagged behind on '{}' and missed {} fluents",
+                self.node_name, fluent_name, skipped);
+          self.lag_report
+              .record(&self.node_name, &fluent_name, skipped);
+
+          if self.lag_policy == LagPolicy::Fail {
+            self.lag_report.failed.notify_one();
+            bail!("'{}' lagged behind on '{}', aborting",
+                  self.node_name,
+                  fluent_name);
+          }
+        }
+        None => return Ok(None),
+      }
+    }
+  }
+}
+
+impl Drop for NodeRx {
+  fn drop(&mut self) {
+    // the fluents the node has not received no longer take up room
+    if self.lag_policy == LagPolicy::Backpressure {
+      self.lag_report.progress.notify_waiters();
+    }
+  }
+}
 
 
 /// Any type implementing this trait can register itself as a node at the
 /// [`Broker`](super::broker::Broker) service.
 pub trait Node: fmt::Debug + Send {
+  /// Provides a name identifying the node in logs and reports.
+  fn name(&self) -> String;
   /// Provides a list of [`Fluent`]s the node publishes.
   fn publishes(&self) -> Vec<String>;
   /// Provides a list of [`Fluent`]s the node subscribes to.
@@ -30,3 +207,81 @@ pub trait Node: fmt::Debug + Send {
   /// Initalizes the node with its [`NodeTx`] and [`NodeRx`] elements.
   fn initialize(&mut self, node_tx: NodeTx, node_rx: NodeRx);
 }
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{LagPolicy, LagReport, NodeRx, NodeTx};
+  use crate::fluent::{Fluent, FluentTrait, Key};
+
+  use pretty_assertions::assert_eq;
+  use std::time::Duration;
+  use tokio::{sync::{broadcast, mpsc},
+              time};
+
+
+  fn fluent(timestamp: usize) -> Fluent {
+    Fluent::new("speed", &[Key::from(1)], timestamp, Box::new(1.0))
+  }
+
+  #[tokio::test]
+  async fn lag_report_test() {
+    let (tx, rx) = broadcast::channel(2);
+    let lag_report = LagReport::default();
+    let mut node_rx = NodeRx::with_lag("handler".to_owned(),
+                                       LagPolicy::Report,
+                                       lag_report.clone());
+    node_rx.subscribe("speed".to_owned(), rx.into());
+
+    for timestamp in 0..5 {
+      tx.send(fluent(timestamp)).unwrap();
+    }
+    drop(tx);
+
+    let (name, received) = node_rx.recv().await.unwrap().unwrap();
+    assert_eq!(name, "speed");
+    assert_eq!(received.timestamp(), 3);
+    assert_eq!(node_rx.recv().await.unwrap().unwrap().1.timestamp(), 4);
+    assert!(node_rx.recv().await.unwrap().is_none());
+
+    let skipped = lag_report.skipped();
+    assert_eq!(skipped.get(&("handler".to_owned(), "speed".to_owned())),
+               Some(&3));
+  }
+
+  #[tokio::test]
+  async fn lag_fail_test() {
+    let (tx, rx) = broadcast::channel(2);
+    let lag_report = LagReport::default();
+    let mut node_rx =
+      NodeRx::with_lag("sink".to_owned(), LagPolicy::Fail, lag_report.clone());
+    node_rx.subscribe("speed".to_owned(), rx.into());
+
+    for timestamp in 0..5 {
+      tx.send(fluent(timestamp)).unwrap();
+    }
+
+    assert!(node_rx.recv().await.is_err());
+    // completes immediately, since the failure has been signalled
+    lag_report.failed().await;
+  }
+
+  #[tokio::test]
+  async fn bounded_node_tx_test() {
+    let (tx, mut rx) = mpsc::channel(2);
+    let node_tx = NodeTx::from(tx);
+
+    node_tx.send(fluent(0)).await.unwrap();
+    node_tx.send(fluent(1)).await.unwrap();
+    // the channel is full, so sending waits until there is room again
+    let send = node_tx.send(fluent(2));
+    let sent = time::timeout(Duration::from_millis(10), send).await;
+    assert!(sent.is_err());
+
+    assert_eq!(rx.recv().await.unwrap().timestamp(), 0);
+    node_tx.send(fluent(2)).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().timestamp(), 1);
+    assert_eq!(rx.recv().await.unwrap().timestamp(), 2);
+  }
+}
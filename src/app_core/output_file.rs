@@ -0,0 +1,480 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::sink::{EventRow, EventValue};
+
+use eyre::{bail, eyre, Result};
+use parquet::{data_type::{BoolType,
+                          ByteArray,
+                          ByteArrayType,
+                          DataType,
+                          DoubleType,
+                          Int64Type},
+              file::{properties::WriterProperties,
+                     writer::{SerializedFileWriter,
+                              SerializedRowGroupWriter}},
+              schema::parser::parse_message_type};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{fs::File,
+          io::{BufWriter, Write},
+          path::{Path, PathBuf},
+          sync::Arc};
+
+
+/// Schema of the Parquet files written. Of the value columns, only the one
+/// matching the `value_type` is set.
+const PARQUET_SCHEMA: &str = "
+  message event {
+    required binary fluent_name (UTF8);
+    repeated binary keys (UTF8);
+    required int64 timestamp;
+    required binary value_type (UTF8);
+    optional boolean bool_value;
+    optional int64 int_value;
+    optional double float_value;
+    optional binary text_value (UTF8);
+    optional double point_x;
+    optional double point_y;
+    required int64 last_change;
+  }
+";
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Formats an [`OutputFile`] can be written in.
+pub enum OutputFormat {
+  Csv,
+  JsonLines,
+  Parquet,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// Holds parameters for writing to files with a [`Sink`](super::sink::Sink).
+pub struct OutputFileParams {
+  pub path:        String,
+  #[serde(default)]
+  pub rotate_rows: Option<usize>,
+}
+
+
+/// Writer for the format of the file currently open.
+enum FormatWriter {
+  Csv(csv::Writer<File>),
+  JsonLines(BufWriter<File>),
+  Parquet(SerializedFileWriter<File>),
+}
+
+
+/// File an event stream is written to. If a number of rows to rotate after is
+/// given, a new file is started once that many rows have been written, and
+/// files are numbered, i.e. `events.csv` is written as `events.0.csv`,
+/// `events.1.csv` and so on.
+pub struct OutputFile {
+  format:       OutputFormat,
+  params:       OutputFileParams,
+  index:        usize,
+  rows_in_file: usize,
+  writer:       Option<FormatWriter>,
+}
+
+impl std::fmt::Debug for OutputFile {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("OutputFile")
+     .field("format", &self.format)
+     .field("params", &self.params)
+     .field("index", &self.index)
+     .field("rows_in_file", &self.rows_in_file)
+     .finish()
+  }
+}
+
+impl OutputFile {
+  pub fn new(format: OutputFormat, params: OutputFileParams) -> Result<Self> {
+    if params.rotate_rows == Some(0) {
+      bail!("cannot rotate output file '{}' after 0 rows", params.path);
+    }
+
+    Ok(Self { format,
+              params,
+              index: 0,
+              rows_in_file: 0,
+              writer: None })
+  }
+
+  /// Writes the rows, opening a new file first if none is open or the current
+  /// one is full. A batch of rows may be split across files.
+  pub fn write(&mut self, rows: &[EventRow]) -> Result<()> {
+    let mut rows = rows;
+    while !rows.is_empty() {
+      let capacity = match self.params.rotate_rows {
+        Some(rotate_rows) if self.rows_in_file >= rotate_rows => {
+          self.close_current()?;
+          rotate_rows
+        }
+        Some(rotate_rows) => rotate_rows - self.rows_in_file,
+        None => rows.len(),
+      };
+      if self.writer.is_none() {
+        self.writer = Some(self.open()?);
+      }
+
+      let (batch, rest) = rows.split_at(std::cmp::min(capacity, rows.len()));
+      // unwrap here is safe: we've just opened the writer
+      match self.writer.as_mut().unwrap() {
+        FormatWriter::Csv(writer) => write_csv(writer, batch)?,
+        FormatWriter::JsonLines(writer) => write_json_lines(writer, batch)?,
+        FormatWriter::Parquet(writer) => write_parquet(writer, batch)?,
+      }
+      self.rows_in_file += batch.len();
+      rows = rest;
+    }
+    Ok(())
+  }
+
+  /// Finishes the file currently open. Parquet files are only readable once
+  /// they have been closed.
+  pub fn close(&mut self) -> Result<()> {
+    self.close_current()
+  }
+
+  /// Path of the file currently written to.
+  fn path(&self) -> PathBuf {
+    let path = Path::new(&self.params.path);
+    if self.params.rotate_rows.is_none() {
+      return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+      Some(ext) => path.with_file_name(format!("{}.{}.{}",
+                                               stem,
+                                               self.index,
+                                               ext.to_string_lossy())),
+      None => path.with_file_name(format!("{}.{}", stem, self.index)),
+    }
+  }
+
+  fn open(&self) -> Result<FormatWriter> {
+    let file = File::create(self.path())?;
+    Ok(match self.format {
+         OutputFormat::Csv => {
+           let mut writer = csv::Writer::from_writer(file);
+           writer.write_record(["fluent_name",
+                                "keys",
+                                "timestamp",
+                                "value_type",
+                                "value",
+                                "last_change"])?;
+           FormatWriter::Csv(writer)
+         }
+         OutputFormat::JsonLines => {
+           FormatWriter::JsonLines(BufWriter::new(file))
+         }
+         OutputFormat::Parquet => {
+           let schema = Arc::new(parse_message_type(PARQUET_SCHEMA)?);
+           let properties = Arc::new(WriterProperties::builder().build());
+           FormatWriter::Parquet(SerializedFileWriter::new(file, schema,
+                                                           properties)?)
+         }
+       })
+  }
+
+  fn close_current(&mut self) -> Result<()> {
+    match self.writer.take() {
+      Some(FormatWriter::Csv(mut writer)) => writer.flush()?,
+      Some(FormatWriter::JsonLines(mut writer)) => writer.flush()?,
+      Some(FormatWriter::Parquet(writer)) => {
+        writer.close()?;
+      }
+      None => return Ok(()),
+    }
+    self.index += 1;
+    self.rows_in_file = 0;
+    Ok(())
+  }
+}
+
+
+fn write_csv(writer: &mut csv::Writer<File>, rows: &[EventRow]) -> Result<()> {
+  for row in rows {
+    writer.write_record([row.name.clone(),
+                         row.keys.join("|"),
+                         row.timestamp.to_string(),
+                         row.value.value_type.to_owned(),
+                         value_text(&row.value),
+                         row.last_change.to_string()])?;
+  }
+  Ok(writer.flush()?)
+}
+
+fn write_json_lines(writer: &mut BufWriter<File>,
+                    rows: &[EventRow])
+                    -> Result<()> {
+  for row in rows {
+    let record = json!({
+      "fluent_name": row.name,
+      "keys": row.keys,
+      "timestamp": row.timestamp,
+      "value_type": row.value.value_type,
+      "value": value_json(&row.value),
+      "last_change": row.last_change,
+    });
+    writeln!(writer, "{}", record)?;
+  }
+  Ok(writer.flush()?)
+}
+
+fn write_parquet(writer: &mut SerializedFileWriter<File>,
+                 rows: &[EventRow])
+                 -> Result<()> {
+  let mut row_group = writer.next_row_group()?;
+
+  let names = rows.iter()
+                  .map(|r| ByteArray::from(r.name.as_str()))
+                  .collect::<Vec<_>>();
+  write_column::<ByteArrayType>(&mut row_group, &names, None, None)?;
+
+  // keys are a repeated column: each key is defined, the first of each row
+  // starts a new row, rows without keys have a single undefined entry
+  let (mut keys, mut def_levels, mut rep_levels) = (vec![], vec![], vec![]);
+  for row in rows {
+    if row.keys.is_empty() {
+      def_levels.push(0);
+      rep_levels.push(0);
+    }
+    for (i, key) in row.keys.iter().enumerate() {
+      keys.push(ByteArray::from(key.as_str()));
+      def_levels.push(1);
+      rep_levels.push(if i == 0 { 0 } else { 1 });
+    }
+  }
+  write_column::<ByteArrayType>(&mut row_group,
+                                &keys,
+                                Some(&def_levels),
+                                Some(&rep_levels))?;
+
+  let timestamps = rows.iter().map(|r| r.timestamp).collect::<Vec<_>>();
+  write_column::<Int64Type>(&mut row_group, &timestamps, None, None)?;
+
+  let value_types = rows.iter()
+                        .map(|r| ByteArray::from(r.value.value_type))
+                        .collect::<Vec<_>>();
+  write_column::<ByteArrayType>(&mut row_group, &value_types, None, None)?;
+
+  let (values, def_levels) = optional(rows, |v| v.bool_value);
+  write_column::<BoolType>(&mut row_group, &values, Some(&def_levels), None)?;
+  let (values, def_levels) = optional(rows, |v| v.int_value);
+  write_column::<Int64Type>(&mut row_group, &values, Some(&def_levels), None)?;
+  let (values, def_levels) = optional(rows, |v| v.float_value);
+  write_column::<DoubleType>(&mut row_group,
+                             &values,
+                             Some(&def_levels),
+                             None)?;
+  let (values, def_levels) =
+    optional(rows, |v| v.text_value.as_deref().map(ByteArray::from));
+  write_column::<ByteArrayType>(&mut row_group,
+                                &values,
+                                Some(&def_levels),
+                                None)?;
+  let (values, def_levels) =
+    optional(rows, |v| v.point_value.as_ref().map(|p| p.0 .0));
+  write_column::<DoubleType>(&mut row_group,
+                             &values,
+                             Some(&def_levels),
+                             None)?;
+  let (values, def_levels) =
+    optional(rows, |v| v.point_value.as_ref().map(|p| p.0 .1));
+  write_column::<DoubleType>(&mut row_group,
+                             &values,
+                             Some(&def_levels),
+                             None)?;
+
+  let last_changes = rows.iter().map(|r| r.last_change).collect::<Vec<_>>();
+  write_column::<Int64Type>(&mut row_group, &last_changes, None, None)?;
+
+  row_group.close()?;
+  Ok(())
+}
+
+/// Writes the next column of the row group.
+fn write_column<T: DataType>(row_group: &mut SerializedRowGroupWriter<'_,
+                                                           File>,
+                             values: &[T::T],
+                             def_levels: Option<&[i16]>,
+                             rep_levels: Option<&[i16]>)
+                             -> Result<()> {
+  let mut column =
+    row_group.next_column()?
+             .ok_or_else(|| eyre!("parquet schema is missing a column"))?;
+  column.typed::<T>()
+        .write_batch(values, def_levels, rep_levels)?;
+  column.close()?;
+  Ok(())
+}
+
+/// Splits an optional value column into the values set and the definition
+/// levels of all rows.
+fn optional<T, F>(rows: &[EventRow], f: F) -> (Vec<T>, Vec<i16>)
+  where F: Fn(&EventValue) -> Option<T> {
+  let mut values = Vec::new();
+  let mut def_levels = Vec::new();
+  for row in rows {
+    match f(&row.value) {
+      Some(value) => {
+        values.push(value);
+        def_levels.push(1);
+      }
+      None => def_levels.push(0),
+    }
+  }
+  (values, def_levels)
+}
+
+/// Text representation of the value, with points as `(x,y)`.
+fn value_text(value: &EventValue) -> String {
+  if let Some(value) = value.bool_value {
+    return value.to_string();
+  }
+  if let Some(value) = value.int_value {
+    return value.to_string();
+  }
+  if let Some(value) = value.float_value {
+    return value.to_string();
+  }
+  if let Some(value) = &value.text_value {
+    return value.to_owned();
+  }
+  match &value.point_value {
+    Some(point) => format!("({},{})", point.0 .0, point.0 .1),
+    None => String::new(),
+  }
+}
+
+/// JSON representation of the value, with points as `[x, y]`.
+fn value_json(value: &EventValue) -> Value {
+  if let Some(value) = value.bool_value {
+    return json!(value);
+  }
+  if let Some(value) = value.int_value {
+    return json!(value);
+  }
+  if let Some(value) = value.float_value {
+    return json!(value);
+  }
+  if let Some(value) = &value.text_value {
+    return json!(value);
+  }
+  match &value.point_value {
+    Some(point) => json!([point.0 .0, point.0 .1]),
+    None => Value::Null,
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{OutputFile, OutputFileParams, OutputFormat};
+  use crate::{app_core::sink::EventRow,
+              fluent::{Fluent, Key}};
+
+  use indoc::indoc;
+  use parquet::file::reader::{FileReader, SerializedFileReader};
+  use pretty_assertions::assert_eq;
+  use std::{fs::{self, File},
+            path::{Path, PathBuf}};
+
+
+  fn rows() -> Vec<EventRow> {
+    let keys = &[Key::from(23), Key::from(42)];
+    let point = Box::new((1.0, 2.0));
+    let fluents = [Fluent::new("near_coast", keys, 10, Box::new(true)),
+                   Fluent::new("distance", keys, 11, Box::new(4.2)),
+                   Fluent::new("location", &[Key::from(23)], 12, point)];
+
+    fluents.iter().filter_map(EventRow::from_fluent).collect()
+  }
+
+  fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("magritte_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn write(format: OutputFormat, path: &Path, rotate_rows: Option<usize>) {
+    let params = OutputFileParams { path: path.to_string_lossy()
+                                              .to_string(),
+                                    rotate_rows };
+    let mut output_file = OutputFile::new(format, params).unwrap();
+    output_file.write(&rows()).unwrap();
+    output_file.close().unwrap();
+  }
+
+  #[test]
+  fn csv_output_test() {
+    let path = output_dir("csv_output").join("events.csv");
+    write(OutputFormat::Csv, &path, None);
+
+    assert_eq!(fs::read_to_string(path).unwrap(),
+               indoc! {r#"
+                 fluent_name,keys,timestamp,value_type,value,last_change
+                 near_coast,23|42,10,boolean,true,10
+                 distance,23|42,11,float_pt,4.2,11
+                 location,23,12,plane_pt,"(1,2)",12
+               "#});
+  }
+
+  #[test]
+  fn json_lines_output_test() {
+    let path = output_dir("json_lines_output").join("events.ndjson");
+    write(OutputFormat::JsonLines, &path, None);
+
+    let lines =
+      fs::read_to_string(path).unwrap()
+                              .lines()
+                              .map(|l| serde_json::from_str(l).unwrap())
+                              .collect::<Vec<serde_json::Value>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["keys"], serde_json::json!(["23", "42"]));
+    assert_eq!(lines[1]["value"], serde_json::json!(4.2));
+    assert_eq!(lines[2]["value"], serde_json::json!([1.0, 2.0]));
+    assert_eq!(lines[2]["value_type"], serde_json::json!("plane_pt"));
+  }
+
+  #[test]
+  fn parquet_output_test() {
+    let path = output_dir("parquet_output").join("events.parquet");
+    write(OutputFormat::Parquet, &path, None);
+
+    let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+    assert_eq!(reader.metadata()
+                     .file_metadata()
+                     .schema()
+                     .get_fields()
+                     .len(),
+               11);
+  }
+
+  #[test]
+  fn rotation_test() {
+    let dir = output_dir("rotation");
+    write(OutputFormat::JsonLines, &dir.join("events.ndjson"), Some(2));
+
+    let lines =
+      |name: &str| fs::read_to_string(dir.join(name)).unwrap().lines().count();
+    assert_eq!(lines("events.0.ndjson"), 2);
+    assert_eq!(lines("events.1.ndjson"), 1);
+    assert!(!dir.join("events.ndjson").exists());
+
+    let params = OutputFileParams { path:        String::from("events.csv"),
+                                    rotate_rows: Some(0), };
+    assert!(OutputFile::new(OutputFormat::Csv, params).is_err());
+  }
+}
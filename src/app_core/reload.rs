@@ -0,0 +1,331 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::{broker::Registrar, database::Database, graph::FluentGraph};
+use crate::{fluent::TimeUnit,
+            handler::{Lateness, Rule}};
+
+use eyre::Result;
+use std::{collections::BTreeMap,
+          fs,
+          sync::Arc,
+          time::{Duration, SystemTime}};
+use tokio::{sync::Notify, task::JoinHandle, time};
+use tracing::{info, warn};
+
+
+#[derive(Debug)]
+/// Keeps the handlers declared as [`Rule`]s in line with the rule file while
+/// the application is running. Once the file has changed, rules which
+/// are new or have changed are started and registered at the running
+/// [`Broker`](super::broker::Broker), and rules which have changed or been
+/// removed are retired. Handlers of unchanged rules keep running, and with
+/// them their state.
+pub struct RuleReload {
+  path:           String,
+  interval:       Duration,
+  buffer_timeout: usize,
+  time_unit:      TimeUnit,
+  lateness:       Lateness,
+  database:       Database,
+  registrar:      Registrar,
+  graph:          FluentGraph,
+  modified:       Option<SystemTime>,
+  running:        BTreeMap<String, (Rule, JoinHandle<Result<()>>)>,
+}
+
+impl RuleReload {
+  /// Creates a [`RuleReload`] for the rule file at `path`, checking it for
+  /// changes every `interval` seconds. `graph` is the graph of all nodes of
+  /// the run, including the handlers of the rules `running`.
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(path: String,
+             interval: u64,
+             buffer_timeout: usize,
+             time_unit: TimeUnit,
+             lateness: Lateness,
+             database: Database,
+             registrar: Registrar,
+             graph: FluentGraph,
+             running: Vec<(Rule, JoinHandle<Result<()>>)>)
+             -> Self {
+    let modified = modified(&path);
+    let running =
+      running.into_iter()
+             .map(|(rule, task)| (rule.fluent_name.clone(), (rule, task)))
+             .collect();
+    Self { path,
+           interval: Duration::from_secs(interval),
+           buffer_timeout,
+           time_unit,
+           lateness,
+           database,
+           registrar,
+           graph,
+           modified,
+           running }
+  }
+
+  /// Checks the rule file for changes until `stop` is notified, reloading it
+  /// whenever it has changed. Returns the tasks of the handlers running
+  /// at that point.
+  pub async fn run(mut self,
+                   stop: Arc<Notify>)
+                   -> Vec<JoinHandle<Result<()>>> {
+    let mut interval = time::interval(self.interval);
+    loop {
+      tokio::select! {
+        _ = interval.tick() => {
+          let modified = modified(&self.path);
+          if modified == self.modified {
+            continue;
+          }
+          self.modified = modified;
+
+          // an invalid rule file leaves the running handlers as they are
+          if let Err(err) = self.reload().await {
+            warn!("unable to reload rules from '{}': {:?}", self.path, err);
+          }
+        }
+        _ = stop.notified() => break,
+      }
+    }
+    self.running.into_values().map(|(_, task)| task).collect()
+  }
+
+  /// Reloads the rule file, checking the resulting graph before anything is
+  /// started or retired. As sinks are not reloaded, new rules need to be
+  /// subscribed to by a sink from the start, unless unconsumed outputs are
+  /// allowed.
+  async fn reload(&mut self) -> Result<()> {
+    let rules = Rule::load(&self.path)?;
+
+    let removed =
+      self.running
+          .keys()
+          .filter(|name| !rules.iter().any(|rule| &rule.fluent_name == *name))
+          .cloned()
+          .collect::<Vec<_>>();
+    let mut started = Vec::new();
+    for rule in rules {
+      if matches!(self.running.get(&rule.fluent_name),
+                  Some((running, _)) if *running == rule)
+      {
+        continue;
+      }
+      let handler = rule.handler(self.buffer_timeout,
+                                 self.time_unit,
+                                 self.lateness.clone(),
+                                 self.database.clone())
+                        .await?;
+      started.push((rule, handler));
+    }
+
+    // the handlers of changed rules are replaced by the ones started
+    let mut graph = self.graph.clone();
+    for fluent_name in removed.iter() {
+      graph.remove_handler(fluent_name);
+    }
+    for (rule, handler) in started.iter() {
+      graph.remove_handler(&rule.fluent_name);
+      graph.add_handler(handler);
+    }
+    graph.validate()?;
+    self.graph = graph;
+
+    // new handlers are registered before the ones they replace are retired,
+    // so the channel of a fluent they both publish stays open throughout
+    let mut replaced = Vec::new();
+    for (rule, mut handler) in started {
+      self.registrar.register(&mut handler).await?;
+      let task = tokio::spawn(handler.run());
+
+      info!("started handler for rule '{}'", rule.fluent_name);
+      if let Some((_, task)) =
+        self.running.insert(rule.fluent_name.clone(), (rule, task))
+      {
+        replaced.push(task);
+      }
+    }
+    // aborting a handler also aborts the tasks applying and publishing its
+    // pending evaluations; it is waited for to have stopped publishing
+    for task in replaced {
+      task.abort();
+      let _ = task.await;
+    }
+    for fluent_name in removed {
+      if let Some((_, task)) = self.running.remove(&fluent_name) {
+        info!("retired handler for rule '{}'", fluent_name);
+        task.abort();
+        let _ = task.await;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+
+/// Returns when the file at `path` was last modified, if that can be told.
+fn modified(path: &str) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|metadata| metadata.modified())
+                    .ok()
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::RuleReload;
+  use crate::{app_core::{broker::Broker,
+                         graph::{FluentGraph, NodeKind},
+                         testing::{self, TestNode}},
+              fluent::{Fluent, FluentTrait, Key, TimeUnit},
+              handler::{Lateness, Rule},
+              stringvec};
+
+  use indoc::indoc;
+  use pretty_assertions::assert_eq;
+  use std::fs;
+
+
+  fn fluent(name: &str, timestamp: usize, value: f64) -> Fluent {
+    Fluent::new(name, &[Key::from(1)], timestamp, Box::new(value))
+  }
+
+  async fn received(node: TestNode) -> Vec<(String, usize, bool)> {
+    let (_, mut node_rx) = node.node_ch.unwrap();
+    let mut received = Vec::new();
+    while let Some((_, fluent)) = node_rx.recv().await.unwrap() {
+      received.push((fluent.name().to_owned(),
+                     fluent.timestamp(),
+                     fluent.value::<bool>()));
+    }
+    received
+  }
+
+  #[tokio::test]
+  async fn reload_test() {
+    let path = std::env::temp_dir().join("magritte_reload_test.toml");
+    let path = path.to_str().unwrap().to_owned();
+    fs::write(&path,
+              indoc! {r#"
+                [[rule]]
+                fluent_name = "fast"
+                dependencies = ["speed", "heading"]
+                expression = "speed > 5.0"
+
+                [[rule]]
+                fluent_name = "medium"
+                dependencies = ["speed"]
+                expression = "speed > 3.0"
+
+                [[rule]]
+                fluent_name = "slow"
+                dependencies = ["speed"]
+                expression = "speed < 1.0"
+              "#}).unwrap();
+    let database = testing::database();
+    let mut broker: Broker = toml::from_str(indoc! {r#"
+      broadcast_capacity = 16
+      timeout = 1
+    "#}).unwrap();
+    broker.keep_alive();
+
+    let mut source = TestNode { publishes: stringvec!["speed", "heading"],
+                                ..Default::default() };
+    let mut sink = TestNode { subscribes_to: stringvec!["fast"],
+                              ..Default::default() };
+    broker.register(&mut source);
+    broker.register(&mut sink);
+    // the sink of the rules started by the reload is registered afterwards
+    let mut graph = FluentGraph::new();
+    graph.allow_unconsumed();
+    graph.add(NodeKind::Source, &source);
+    graph.add(NodeKind::Sink, &sink);
+
+    let mut running = Vec::new();
+    for rule in Rule::load(&path).unwrap() {
+      let mut handler = rule.handler(60,
+                                     TimeUnit::Seconds,
+                                     Lateness::default(),
+                                     database.clone())
+                            .await
+                            .unwrap();
+      broker.register(&mut handler);
+      graph.add_handler(&handler);
+      running.push((rule, tokio::spawn(handler.run())));
+    }
+
+    let registrar = broker.registrar();
+    let runner = tokio::spawn(broker.run());
+    let mut reload = RuleReload::new(path.clone(),
+                                     1,
+                                     60,
+                                     TimeUnit::Seconds,
+                                     Lateness::default(),
+                                     database,
+                                     registrar.clone(),
+                                     graph,
+                                     running);
+
+    // 'fast' holds on to the speed until the heading arrives
+    let (node_tx, _) = source.node_ch.take().unwrap();
+    node_tx.send(fluent("speed", 100, 7.0)).await.unwrap();
+
+    // 'fast' is unchanged, 'medium' is replaced, 'slow' is retired and
+    // 'very_fast' is started
+    fs::write(&path,
+              indoc! {r#"
+                [[rule]]
+                fluent_name = "fast"
+                dependencies = ["speed", "heading"]
+                expression = "speed > 5.0"
+
+                [[rule]]
+                fluent_name = "medium"
+                dependencies = ["speed"]
+                expression = "speed > 20.0"
+
+                [[rule]]
+                fluent_name = "very_fast"
+                dependencies = ["speed"]
+                expression = "speed > 10.0"
+              "#}).unwrap();
+    reload.reload().await.unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(reload.running.keys().collect::<Vec<_>>(),
+               vec!["fast", "medium", "very_fast"]);
+
+    let mut late_sink = TestNode { subscribes_to: stringvec!["medium",
+                                                             "very_fast"],
+                                   ..Default::default() };
+    registrar.register(&mut late_sink).await.unwrap();
+
+    // the speed 'fast' held on to before the reload is still there. its
+    // subscriptions are not ordered among each other, so the heading is
+    // evaluated before the next speed is sent
+    node_tx.send(fluent("heading", 100, 90.0)).await.unwrap();
+    let (_, node_rx) = sink.node_ch.as_mut().unwrap();
+    let (_, fast) = node_rx.recv().await.unwrap().unwrap();
+    assert_eq!((fast.timestamp(), fast.value::<bool>()), (100, true));
+    node_tx.send(fluent("speed", 101, 12.0)).await.unwrap();
+    drop(node_tx);
+
+    assert_eq!(received(sink).await, Vec::new());
+    // either 'medium' may have evaluated the speed at 100 as well
+    let mut late = received(late_sink).await;
+    late.retain(|&(_, timestamp, _)| timestamp == 101);
+    late.sort();
+    assert_eq!(late,
+               vec![("medium".to_owned(), 101, false),
+                    ("very_fast".to_owned(), 101, true)]);
+
+    drop(reload);
+    drop(registrar);
+    assert!(runner.await.unwrap().is_ok());
+  }
+}
@@ -4,91 +4,488 @@
 // received a copy of this license along with the source code. If that is not
 // the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
 
-use super::{Node, NodeRx, NodeTx};
+use super::{intervals::{IntervalRow, Intervals},
+            output_file::{OutputFile, OutputFileParams, OutputFormat},
+            util::PointColumn,
+            Node,
+            NodeRx,
+            NodeTx};
 use crate::{fluent::{Fluent, FluentTrait},
+            handler::{Emit, EmitFilter},
             sqlvec};
 
-use eyre::{bail, eyre, Result};
+use eyre::{bail, eyre, Result, WrapErr};
 use serde::Deserialize;
-use std::time::Duration;
-use tokio::time;
-use tokio_postgres::{types::ToSql, Client};
-use tokio_stream::StreamExt;
-use tracing::{debug, info};
+use std::{future::Future,
+          mem,
+          sync::{Arc, Mutex},
+          time::Duration};
+use tokio::{task, time};
+use tokio_postgres::{binary_copy::BinaryCopyInWriter,
+                     types::{ToSql, Type},
+                     Client};
+use tracing::{debug, warn};
+
+
+/// Time waited before the first retry of a failed write, doubled for each
+/// further one.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
 
 
 #[derive(Debug, Deserialize)]
 /// Receives [`Fluent`]s from the [`Broker`](super::broker::Broker)
-/// service and writes them to the PostgreSQL database.
+/// service and writes them to the PostgreSQL database, or to a CSV, JSON lines
+/// or Parquet file, see [`OutputFile`]. In the `events` mode, fluents of any
+/// value type but `Instant` are written as they are received; in the
+/// `intervals` mode, which requires the database, boolean fluents are folded
+/// into the intervals during which they held, see [`Intervals`].
 pub struct Sink {
-  debug:         bool,
-  write_timeout: usize,
-  subscribes_to: Vec<String>,
+  debug:            bool,
+  write_timeout:    usize,
+  #[serde(default)]
+  kind:             SinkKind,
+  #[serde(default)]
+  file_params:      Option<OutputFileParams>,
+  #[serde(default)]
+  mode:             SinkMode,
+  #[serde(default)]
+  interval_timeout: Option<usize>,
+  #[serde(default)]
+  emit:             Emit,
+  #[serde(default = "default_batch_size")]
+  batch_size:       usize,
+  #[serde(default = "default_flush_interval")]
+  flush_interval:   u64,
+  #[serde(default = "default_write_retries")]
+  write_retries:    usize,
+  subscribes_to:    Vec<String>,
   #[serde(skip)]
-  node_rx:       Option<NodeRx>,
+  node_rx:          Option<NodeRx>,
+}
+
+fn default_batch_size() -> usize {
+  1_000
+}
+
+fn default_flush_interval() -> u64 {
+  1_000
+}
+
+fn default_write_retries() -> usize {
+  3
 }
 
 impl Sink {
+  /// Returns whether the [`Sink`] writes to the database, i.e. requires a
+  /// database client to run.
+  pub fn requires_database(&self) -> bool {
+    self.kind == SinkKind::Postgres
+  }
+
+  /// Checks the parameters of the [`Sink`] which deserialize fine but cannot
+  /// be run with: a `flush_interval` of zero, or a `write_timeout` of zero,
+  /// which only has a meaning in debug mode, where it prints the fluents
+  /// received instead of writing them.
+  pub fn validate(&self) -> Result<()> {
+    if self.flush_interval == 0 {
+      bail!("Sink flush_interval must be positive, is 0");
+    }
+    if self.write_timeout == 0 && !self.debug {
+      bail!("Sink write_timeout must be positive, is 0; to print the \
+             fluents received instead of writing them, set debug = true");
+    }
+    Ok(())
+  }
+
   /// Runs the [`Sink`], receiving fluents from the
-  /// [`Broker`](super::broker::Broker) and writing them to the database.
-  /// Consumes the original object.
+  /// [`Broker`](super::broker::Broker) and writing them in batches, each batch
+  /// once it is full or the flush interval has passed. Rows still buffered
+  /// when the [`Broker`](super::broker::Broker) stops, or the [`Sink`] fails
+  /// for lagging behind, are written before returning, as are intervals still
+  /// open. A batch which cannot be written is retried with backoff, up to
+  /// `write_retries` times, before the [`Sink`] fails with its rows still
+  /// buffered. Consumes the original object.
   pub async fn run(self, database_client: Option<Client>) -> Result<()> {
-    let database_client =
-      database_client.ok_or(eyre!("Sink requires a database client"))?;
+    let mut destination = match self.kind.output_format() {
+      None => {
+        Destination::Database(database_client.ok_or(eyre!("Sink requires \
+                                                           a database \
+                                                           client"))?)
+      }
+      Some(format) => {
+        if matches!(self.mode, SinkMode::Intervals) {
+          bail!("Sink writes intervals to the database only");
+        }
+        let file_params =
+          self.file_params
+              .ok_or(eyre!("Sink of kind {:?} requires file_params",
+                           self.kind))?;
+        let output_file = OutputFile::new(format, file_params)?;
+        Destination::File(Arc::new(Mutex::new(output_file)))
+      }
+    };
 
     let mut node_rx = match self.node_rx {
       Some(node_rx) => node_rx,
       None => bail!("Sink not initialized, aborting"),
     };
 
-    let statement_raw = include_str!("./sql/sink.sql");
-    let statement = match database_client.prepare(statement_raw).await {
-      Ok(statement) => statement,
-      Err(err) => {
-        panic!("Error in Sink PostgreSQL statement: {}", err);
-      }
-    };
     let timeout = Duration::from_millis(self.write_timeout as u64);
+    let retries = self.write_retries;
+    let mut flush_interval =
+      time::interval(Duration::from_millis(self.flush_interval));
+    let mut events = Vec::new();
+    let mut intervals = Vec::new();
+    let mut emit_filter = EmitFilter::new(self.emit);
+    let mut folder = match self.mode {
+      SinkMode::Events => None,
+      SinkMode::Intervals => Some(Intervals::new(self.interval_timeout)),
+    };
 
-    while let Some((_, Ok(fluent))) = node_rx.next().await {
-      // write only Boolean fluents to database
-      if self.debug || !matches!(fluent, Fluent::Boolean(_)) {
-        if self.write_timeout == 0 {
-          println!("{},{},{:?},{}",
-                   fluent.timestamp(),
-                   fluent.name(),
-                   fluent.boxed_value(),
-                   fluent.keys()
-                         .iter()
-                         .map(|k| format!("{}", k))
-                         .collect::<Vec<_>>()
-                         .join("|"));
+    let mut lagged = None;
+    loop {
+      tokio::select! {
+        next = node_rx.recv() => match next {
+          Ok(Some((_, fluent))) => {
+            if !emit_filter.admits(&fluent) {
+              continue;
+            }
+            if self.debug {
+              if self.write_timeout == 0 {
+                println!("{},{},{:?},{}",
+                         fluent.timestamp(),
+                         fluent.name(),
+                         fluent.boxed_value(),
+                         fluent.keys()
+                               .iter()
+                               .map(|k| format!("{}", k))
+                               .collect::<Vec<_>>()
+                               .join("|"));
+              }
+              continue;
+            }
+
+            match &mut folder {
+              Some(folder) => intervals.extend(folder.fold(&fluent)),
+              // instants only have a meaning within this process
+              None => events.extend(EventRow::from_fluent(&fluent)),
+            }
+            if events.len() + intervals.len() < self.batch_size {
+              continue;
+            }
+          }
+          // the broker has stopped: write what is left and finish
+          Ok(None) => break,
+          // the sink has lagged behind under `LagPolicy::Fail`: write what
+          // is left, then fail
+          Err(err) => {
+            lagged = Some(err);
+            break;
+          }
+        },
+        _ = flush_interval.tick() => {
+          if events.is_empty() && intervals.is_empty() {
+            continue;
+          }
         }
-        continue;
       }
 
-      let name = fluent.name();
-      let keys = fluent.keys().iter().map(|&e| e as i32).collect::<Vec<_>>();
-      let timestamp = fluent.timestamp() as i64;
-      let value = fluent.value::<bool>();
-      let last_change = fluent.last_change() as i64;
+      destination.write(&mut events, &mut intervals, timeout, retries)
+                 .await?;
+    }
 
-      let args = sqlvec![&name, &keys, &timestamp, &value, &last_change];
-      let write_future = database_client.execute(&statement, args.as_slice());
+    if let Some(mut folder) = folder {
+      intervals.extend(folder.close_all());
+    }
+    destination.write(&mut events, &mut intervals, timeout, retries)
+               .await?;
+    destination.close().await?;
+    lagged.map_or(Ok(()), Err)
+  }
+}
 
-      match time::timeout(timeout, write_future).await {
-        Ok(result) => match result {
-          Ok(rows_affected) => debug!("{} rows affected", rows_affected),
-          Err(_) => info!("unable to write to database"),
-        },
-        Err(_) => info!("database write timed out"),
+
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Selects where a [`Sink`] writes to.
+enum SinkKind {
+  #[default]
+  Postgres,
+  Csv,
+  JsonLines,
+  Parquet,
+}
+
+impl SinkKind {
+  /// Returns the format of the output file, if the [`Sink`] writes to one.
+  fn output_format(&self) -> Option<OutputFormat> {
+    match self {
+      Self::Postgres => None,
+      Self::Csv => Some(OutputFormat::Csv),
+      Self::JsonLines => Some(OutputFormat::JsonLines),
+      Self::Parquet => Some(OutputFormat::Parquet),
+    }
+  }
+}
+
+
+#[derive(Debug)]
+/// Where a [`Sink`] writes its rows to. Writing files blocks, so it is done
+/// on a thread for blocking tasks, which the file is shared with.
+enum Destination {
+  Database(Client),
+  File(Arc<Mutex<OutputFile>>),
+}
+
+impl Destination {
+  /// Writes and empties the buffered rows, which are kept if they cannot be
+  /// written. Intervals are only ever buffered for the database.
+  async fn write(&mut self,
+                 events: &mut Vec<EventRow>,
+                 intervals: &mut Vec<IntervalRow>,
+                 timeout: Duration,
+                 retries: usize)
+                 -> Result<()> {
+    match self {
+      Self::Database(client) => {
+        write_rows(client, events, timeout, retries).await?;
+        write_rows(client, intervals, timeout, retries).await?;
+      }
+      Self::File(output_file) => {
+        let output_file = output_file.clone();
+        let rows = mem::take(events);
+        let context = format!("unable to write {} rows to file", rows.len());
+        let write = move || {
+          // unwrap here is safe: locking `Mutex` cannot fail
+          let written = output_file.lock().unwrap().write(&rows);
+          (rows, written)
+        };
+        // the rows are kept if they could not be written
+        let (rows, written) = task::spawn_blocking(write).await?;
+        if let Err(err) = written {
+          *events = rows;
+          return Err(err.wrap_err(context));
+        }
       }
     }
     Ok(())
   }
+
+  async fn close(self) -> Result<()> {
+    match self {
+      Self::Database(_) => Ok(()),
+      Self::File(output_file) => {
+        // unwrap here is safe: locking `Mutex` cannot fail
+        let close = move || output_file.lock().unwrap().close();
+        task::spawn_blocking(close).await?
+      }
+    }
+  }
+}
+
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Selects what a [`Sink`] writes to the database.
+enum SinkMode {
+  /// Every fluent received, to the event stream table.
+  #[default]
+  Events,
+  /// The intervals during which boolean fluents held, to the intervals table.
+  Intervals,
+}
+
+
+/// Rows which a [`Sink`] writes to a table using a binary `COPY`.
+pub trait CopyRow {
+  /// The `COPY ... FROM STDIN BINARY` statement for the table.
+  fn statement() -> &'static str;
+  /// Column types of the table, in the order of [`Self::args`].
+  fn types() -> Vec<Type>;
+  /// Values of the row.
+  fn args(&self) -> Vec<&(dyn ToSql + Sync)>;
+}
+
+
+/// Writes the buffered rows to their table using a binary `COPY` and empties
+/// the buffer. As a `COPY` either writes all rows or none, a failed one is
+/// retried as a whole; if all attempts fail, the rows are kept.
+async fn write_rows<R: CopyRow>(client: &Client,
+                                rows: &mut Vec<R>,
+                                timeout: Duration,
+                                retries: usize)
+                                -> Result<()> {
+  if rows.is_empty() {
+    return Ok(());
+  }
+
+  let pending = &*rows;
+  let copy = move || async move {
+    let copy_in_sink = client.copy_in(R::statement()).await?;
+    let writer = BinaryCopyInWriter::new(copy_in_sink, &R::types());
+    tokio::pin!(writer);
+    for row in pending.iter() {
+      writer.as_mut().write(&row.args()).await?;
+    }
+    Ok(writer.finish().await?)
+  };
+
+  let count = rows.len();
+  let context = || format!("unable to write {} rows to database", count);
+  let rows_written =
+    retry(retries, timeout, copy).await.wrap_err_with(context)?;
+  debug!("{} rows written", rows_written);
+  rows.clear();
+  Ok(())
+}
+
+
+/// Runs `attempt` until it succeeds within `timeout`, retrying up to
+/// `retries` times with a backoff doubling from [`RETRY_BACKOFF`]. Returns the
+/// error of the last attempt if none succeeds.
+async fn retry<T, F, Fut>(retries: usize,
+                          timeout: Duration,
+                          mut attempt: F)
+                          -> Result<T>
+  where F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>
+{
+  let mut backoff = RETRY_BACKOFF;
+  let mut retried = 0;
+  loop {
+    let err = match time::timeout(timeout, attempt()).await {
+      Ok(Ok(value)) => return Ok(value),
+      Ok(Err(err)) => err,
+      Err(_) => eyre!("timed out after {:?}", timeout),
+    };
+    if retried == retries {
+      return Err(err);
+    }
+    retried += 1;
+
+    warn!("write failed: {}; retrying in {:?}", err, backoff);
+    time::sleep(backoff).await;
+    backoff *= 2;
+  }
+}
+
+
+#[derive(Debug, PartialEq)]
+/// A row of the event stream table.
+pub(super) struct EventRow {
+  pub(super) name:        String,
+  pub(super) keys:        Vec<String>,
+  pub(super) timestamp:   i64,
+  pub(super) value:       EventValue,
+  pub(super) last_change: i64,
+}
+
+impl EventRow {
+  /// Returns `None` for fluents which cannot be stored, i.e. `Instant`s.
+  pub(super) fn from_fluent(fluent: &Fluent) -> Option<Self> {
+    Some(Self { name:        fluent.name().to_owned(),
+                keys:        fluent.keys()
+                                   .iter()
+                                   .map(|k| k.to_string())
+                                   .collect(),
+                timestamp:   fluent.timestamp() as i64,
+                value:       EventValue::from_fluent(fluent)?,
+                last_change: fluent.last_change() as i64, })
+  }
+}
+
+impl CopyRow for EventRow {
+  /// Copies into the event stream table:
+  ///
+  /// ```sql
+  #[doc = include_str!("./sql/sink.sql")]
+  /// ```
+  fn statement() -> &'static str {
+    include_str!("./sql/sink.sql")
+  }
+
+  fn types() -> Vec<Type> {
+    vec![Type::TEXT,
+         Type::TEXT_ARRAY,
+         Type::INT8,
+         Type::TEXT,
+         Type::BOOL,
+         Type::INT8,
+         Type::FLOAT8,
+         Type::TEXT,
+         Type::POINT,
+         Type::INT8]
+  }
+
+  fn args(&self) -> Vec<&(dyn ToSql + Sync)> {
+    sqlvec![&self.name,
+            &self.keys,
+            &self.timestamp,
+            &self.value.value_type,
+            &self.value.bool_value,
+            &self.value.int_value,
+            &self.value.float_value,
+            &self.value.text_value,
+            &self.value.point_value,
+            &self.last_change]
+  }
 }
 
+
+#[derive(Debug, Default, PartialEq)]
+/// The value of a [`Fluent`] spread over the value columns of the event stream
+/// table, with only the column matching the value type set.
+pub(super) struct EventValue {
+  pub(super) value_type:  &'static str,
+  pub(super) bool_value:  Option<bool>,
+  pub(super) int_value:   Option<i64>,
+  pub(super) float_value: Option<f64>,
+  pub(super) text_value:  Option<String>,
+  pub(super) point_value: Option<PointColumn>,
+}
+
+impl EventValue {
+  /// Returns `None` for fluents which cannot be stored, i.e. `Instant`s.
+  fn from_fluent(fluent: &Fluent) -> Option<Self> {
+    let mut event_value = Self::default();
+    match fluent {
+      Fluent::Textual(_) => {
+        event_value.value_type = "textual";
+        event_value.text_value = Some(fluent.value::<String>());
+      }
+      Fluent::Integer(_) => {
+        event_value.value_type = "integer";
+        event_value.int_value = Some(fluent.value::<i32>() as i64);
+      }
+      Fluent::LongInt(_) => {
+        event_value.value_type = "long_int";
+        event_value.int_value = Some(fluent.value::<i64>());
+      }
+      Fluent::FloatPt(_) => {
+        event_value.value_type = "float_pt";
+        event_value.float_value = Some(fluent.value::<f64>());
+      }
+      Fluent::Boolean(_) => {
+        event_value.value_type = "boolean";
+        event_value.bool_value = Some(fluent.value::<bool>());
+      }
+      Fluent::PlanePt(_) => {
+        event_value.value_type = "plane_pt";
+        event_value.point_value = Some(PointColumn(fluent.value()));
+      }
+      Fluent::Instant(_) => return None,
+    }
+    Some(event_value)
+  }
+}
+
+
 impl Node for Sink {
+  /// `Sink`s are named after the fluents they subscribe to.
+  fn name(&self) -> String {
+    format!("sink of {}", self.subscribes_to.join(", "))
+  }
+
   /// `Sink` publishes no fluents. Implementation returns empty `Vec`.
   fn publishes(&self) -> Vec<String> {
     Vec::new()
@@ -109,27 +506,227 @@ impl Node for Sink {
 
 #[cfg(test)]
 mod tests {
-  use super::{Node, Sink};
-  use crate::stringvec;
+  use super::{retry,
+              CopyRow,
+              Destination,
+              EventRow,
+              EventValue,
+              Node,
+              NodeRx,
+              OutputFile,
+              OutputFileParams,
+              OutputFormat,
+              Sink,
+              SinkKind,
+              SinkMode};
+  use crate::{app_core::{node::{LagPolicy, LagReport},
+                         util::PointColumn},
+              fluent::{Fluent, Key},
+              handler::Emit,
+              stringvec};
 
+  use eyre::eyre;
   use pretty_assertions::assert_eq;
+  use std::{fs,
+            sync::{Arc, Mutex},
+            time::Duration};
+  use tokio::{sync::{broadcast, mpsc},
+              time};
 
   fn sink_init() -> Sink {
-    Sink { debug:         true,
-           write_timeout: 42,
-           subscribes_to: stringvec!["highSpeedNearCoast", "rendezVous"],
-           node_rx:       None, }
+    Sink { debug:            true,
+           write_timeout:    42,
+           kind:             SinkKind::Postgres,
+           file_params:      None,
+           mode:             SinkMode::Events,
+           interval_timeout: None,
+           emit:             Emit::Always,
+           batch_size:       10,
+           flush_interval:   100,
+           write_retries:    3,
+           subscribes_to:    stringvec!["highSpeedNearCoast", "rendezVous"],
+           node_rx:          None, }
   }
 
   #[test]
   fn sink_test() {
     let sink = sink_init();
 
+    assert_eq!(sink.name(), "sink of highSpeedNearCoast, rendezVous");
     assert_eq!(sink.publishes(), Vec::<String>::new());
     assert_eq!(sink.subscribes_to,
                stringvec!["highSpeedNearCoast", "rendezVous"]);
     assert_eq!(sink.subscribes_to(),
                stringvec!["highSpeedNearCoast", "rendezVous"]);
     assert!(sink.node_rx.is_none());
+    assert!(sink.requires_database());
+  }
+
+  #[test]
+  fn validate_test() {
+    assert!(sink_init().validate().is_ok());
+
+    // printing the fluents received in debug mode
+    let sink = Sink { write_timeout: 0,
+                      ..sink_init() };
+    assert!(sink.validate().is_ok());
+
+    let sink = Sink { debug: false,
+                      write_timeout: 0,
+                      ..sink_init() };
+    assert!(sink.validate().is_err());
+    let sink = Sink { flush_interval: 0,
+                      ..sink_init() };
+    assert_eq!(sink.validate().unwrap_err().to_string(),
+               "Sink flush_interval must be positive, is 0");
+  }
+
+  #[tokio::test]
+  async fn retry_test() {
+    let timeout = Duration::from_millis(50);
+    let mut attempts = 0;
+    let result = retry(2, timeout, || {
+                   attempts += 1;
+                   let attempt = attempts;
+                   async move {
+                     match attempt {
+                       1 => Err(eyre!("connection reset")),
+                       _ => Ok(attempt),
+                     }
+                   }
+                 }).await;
+    assert_eq!(result.unwrap(), 2);
+
+    // attempts which take too long count as failed ones
+    let result = retry(1, timeout, || async {
+                   tokio::time::sleep(Duration::from_secs(1)).await;
+                   Ok(())
+                 }).await;
+    assert!(result.is_err());
+  }
+
+  #[tokio::test]
+  async fn file_write_error_test() {
+    let dir = std::env::temp_dir().join("magritte_sink_write_error");
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("events.csv");
+    let file_params = OutputFileParams { path:        path.to_string_lossy()
+                                                          .to_string(),
+                                         rotate_rows: None, };
+    let output_file = OutputFile::new(OutputFormat::Csv, file_params).unwrap();
+    let mut destination = Destination::File(Arc::new(Mutex::new(output_file)));
+
+    let speed = Fluent::new("speed", &[Key::from(1)], 0, Box::new(1.0));
+    let mut events = EventRow::from_fluent(&speed).into_iter().collect();
+    let timeout = Duration::from_millis(50);
+
+    // the directory does not exist yet, so the rows are kept
+    let written = destination.write(&mut events, &mut Vec::new(), timeout, 0)
+                             .await;
+    assert!(written.is_err());
+    assert_eq!(events.len(), 1);
+
+    fs::create_dir_all(&dir).unwrap();
+    destination.write(&mut events, &mut Vec::new(), timeout, 0)
+               .await
+               .unwrap();
+    destination.close().await.unwrap();
+    assert!(events.is_empty());
+    assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 2);
+  }
+
+  #[tokio::test]
+  async fn lag_fail_test() {
+    let dir = std::env::temp_dir().join("magritte_sink_lag_fail");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("events.csv");
+    let file_params = OutputFileParams { path:        path.to_string_lossy()
+                                                          .to_string(),
+                                         rotate_rows: None, };
+    let mut sink = Sink { debug: false,
+                          kind: SinkKind::Csv,
+                          file_params: Some(file_params),
+                          batch_size: 100,
+                          flush_interval: 60_000,
+                          ..sink_init() };
+
+    let (tx, rx) = broadcast::channel(2);
+    let mut node_rx = NodeRx::with_lag("sink".to_owned(),
+                                       LagPolicy::Fail,
+                                       LagReport::default());
+    node_rx.subscribe("speed".to_owned(), rx.into());
+    sink.initialize(mpsc::unbounded_channel().0.into(), node_rx);
+
+    let speed = |timestamp| {
+      Fluent::new("speed", &[Key::from(1)], timestamp, Box::new(1.0))
+    };
+    tx.send(speed(0)).unwrap();
+    tx.send(speed(1)).unwrap();
+    let runner = tokio::spawn(sink.run(None));
+    time::sleep(Duration::from_millis(50)).await;
+    for timestamp in 2..7 {
+      tx.send(speed(timestamp)).unwrap();
+    }
+
+    // the rows buffered before the sink lagged behind are written
+    assert!(runner.await.unwrap().is_err());
+    let written = fs::read_to_string(path).unwrap();
+    assert_eq!(written.lines().count(), 3);
+  }
+
+  #[test]
+  fn event_value_test() {
+    let event_value = |value| {
+      EventValue::from_fluent(&Fluent::new("f", &[Key::from(1)], 0, value))
+    };
+
+    assert_eq!(event_value(Box::new(true)),
+               Some(EventValue { value_type: "boolean",
+                                 bool_value: Some(true),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new(42)),
+               Some(EventValue { value_type: "integer",
+                                 int_value: Some(42),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new(42i64)),
+               Some(EventValue { value_type: "long_int",
+                                 int_value: Some(42),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new(13.37)),
+               Some(EventValue { value_type: "float_pt",
+                                 float_value: Some(13.37),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new(String::from("tug"))),
+               Some(EventValue { value_type: "textual",
+                                 text_value: Some(String::from("tug")),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new((1.0, 2.0))),
+               Some(EventValue { value_type: "plane_pt",
+                                 point_value: Some(PointColumn((1.0,
+                                                                2.0))),
+                                 ..Default::default() }));
+    assert_eq!(event_value(Box::new(std::time::Instant::now())), None);
+  }
+
+  #[test]
+  fn event_row_test() {
+    let keys = &[Key::from(23), Key::from("BREMEN")];
+    let fluent = Fluent::new("near_coast", keys, 1337, Box::new(true));
+    let row = EventRow::from_fluent(&fluent).unwrap();
+
+    assert_eq!(row,
+               EventRow { name:        String::from("near_coast"),
+                          keys:        stringvec!["23", "BREMEN"],
+                          timestamp:   1337,
+                          value:       EventValue { value_type: "boolean",
+                                                    bool_value: Some(true),
+                                                    ..Default::default() },
+                          last_change: 1337, });
+    assert_eq!(row.args().len(), EventRow::types().len());
+
+    let fluent =
+      Fluent::new("instant", keys, 1337, Box::new(std::time::Instant::now()));
+    assert_eq!(EventRow::from_fluent(&fluent), None);
   }
 }
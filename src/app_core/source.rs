@@ -4,42 +4,132 @@
 // received a copy of this license along with the source code. If that is not
 // the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
 
-use super::{Node, NodeRx, NodeTx};
-use crate::fluent::Fluent;
+use super::{clock::{ClockMode, ReplayClock},
+            util::PointColumn,
+            Node,
+            NodeRx,
+            NodeTx};
+use crate::fluent::{Fluent, Key, TimeUnit, Timestamp, ValueType};
 
-use eyre::{bail, eyre, Result};
+use eyre::{bail, eyre, Report, Result};
+use futures::{future,
+              stream::{self, BoxStream},
+              StreamExt,
+              TryStreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::Deserialize;
-use std::time::Instant;
-use tokio::time;
-use tokio_postgres::Client;
-use tracing::info;
+use serde_json::{Map, Value};
+use std::{collections::HashMap, pin::Pin, time::Instant};
+use tokio::{fs::File,
+            io::{AsyncBufReadExt, BufReader},
+            time};
+use tokio_postgres::{types::{FromSql, Type},
+                     Client,
+                     Portal,
+                     Row,
+                     Statement,
+                     Transaction};
+use tracing::{debug, info};
 
 
 #[derive(Debug, Deserialize)]
-/// Reads data from the source (i.e. the PostgreSQL database) and publishes it
-/// to the [`Broker`](super::broker::Broker) service.
+/// Reads data from the source (i.e. the PostgreSQL database or a file) and
+/// publishes it to the [`Broker`](super::broker::Broker) service.
 pub struct Source {
   publishes:    Vec<String>,
+  #[serde(default)]
+  column_types: HashMap<String, ColumnType>,
+  #[serde(default)]
+  kind:         SourceKind,
   run_params:   RunParams,
-  query_params: QueryParams,
+  query_params: Option<QueryParams>,
+  file_params:  Option<FileParams>,
   #[serde(skip)]
   node_tx:      Option<NodeTx>,
 }
 
 impl Source {
-  /// Runs the [`Source`], retrieving data from the database and publishing
-  /// fluents to the [`Broker`](super::broker::Broker). Consumes the original
-  /// object.
+  /// Returns `true` if the [`Source`] reads from the database and thus needs
+  /// to be handed a database client to run.
+  pub fn requires_database(&self) -> bool {
+    self.kind == SourceKind::Postgres
+  }
+
+  /// Returns `true` if the [`Source`] runs in [`RunMode::Live`], i.e. does not
+  /// stop on its own.
+  pub fn is_live(&self) -> bool {
+    self.run_params.mode == RunMode::Live
+  }
+
+  /// Checks the parameters of the [`Source`] which deserialize fine but
+  /// cannot be run with, such as a `fetch_size` which would never fill a
+  /// page or a clock which would never advance.
+  pub fn validate(&self) -> Result<()> {
+    if let Some(qp) = &self.query_params {
+      if qp.fetch_size <= 0 {
+        bail!("Source fetch_size must be positive, is {}", qp.fetch_size);
+      }
+    }
+    self.run_params.clock.validate()
+  }
+
+  /// Returns the unit the timestamps of the data are counted in.
+  pub fn timestamp_unit(&self) -> TimeUnit {
+    self.run_params.timestamp_unit
+  }
+
+  /// Pairs each published fluent name with the type of its column. Columns
+  /// without a declared type are read as [`ColumnType::FloatPt`].
+  fn columns(&self) -> Result<Vec<(String, ColumnType)>> {
+    if let Some(name) = self.column_types
+                            .keys()
+                            .find(|name| !self.publishes.contains(name))
+    {
+      bail!("Source declares a column type for '{}', which it does not \
+             publish",
+            name);
+    }
+
+    Ok(self.publishes
+           .iter()
+           .map(|name| {
+             let column_type =
+               self.column_types.get(name).copied().unwrap_or_default();
+             (name.clone(), column_type)
+           })
+           .collect())
+  }
+
+  /// Runs the [`Source`], retrieving data from the database or a file and
+  /// publishing fluents to the [`Broker`](super::broker::Broker). Consumes the
+  /// original object.
   ///
-  /// Data retrieval is performed using the following SQL:
+  /// Data retrieval from the database is performed using the following SQL:
   ///
   /// ```sql
   #[doc = include_str!("./sql/source.sql")]
   /// ```
+  ///
+  /// Rows are not loaded all at once but fetched in pages of `fetch_size` rows
+  /// (see [`QueryParams`]) as the run progresses, keeping memory bounded.
+  /// Files are read line by line for the same reason and must therefore be
+  /// ordered by timestamp.
+  ///
+  /// In [`RunMode::Live`], the database is instead polled once per cycle for
+  /// rows newer than the latest one seen so far, using the following SQL, and
+  /// the run does not end:
+  /// ```sql
+  #[doc = include_str!("./sql/source_live.sql")]
+  /// ```
+  ///
+  /// If all rows on a page share one timestamp, there may be more of them
+  /// than fit on a page, so all rows with that timestamp are fetched using
+  /// the following SQL before reading on:
+  /// ```sql
+  #[doc = include_str!("./sql/source_live_at.sql")]
+  /// ```
   pub async fn run(self, database_client: Option<Client>) -> Result<()> {
-    let database_client =
-      database_client.ok_or(eyre!("Source requires a database client"))?;
+    let columns = self.columns()?;
 
     let node_tx = match self.node_tx {
       Some(node_tx) => node_tx,
@@ -47,114 +137,106 @@ impl Source {
     };
 
     let rp = self.run_params;
-    let qp = self.query_params;
+    let (start_time, end_time) = rp.window();
 
-    let start_time = rp.big_bang + rp.starting_offset;
-    let end_time =
-      std::cmp::min(start_time + rp.hours_to_run * 3_600, rp.armageddon);
+    if rp.mode == RunMode::Live && self.kind != SourceKind::Postgres {
+      bail!("Source supports live mode only for kind 'postgres'");
+    }
 
     info!("start_time: {} - end_time: {}", start_time, end_time);
 
-    let query_statement_raw = format!(include_str!("./sql/source.sql"),
-                                      key_name = qp.key_name,
-                                      timestamp_name = qp.timestamp_name,
-                                      fluent_names =
-                                        self.publishes.join(", "),
-                                      from_table = qp.from_table,
-                                      start_time = start_time,
-                                      end_time = end_time,
-                                      order_by = qp.order_by);
-
-    let query_statement =
-      match database_client.prepare(&query_statement_raw).await {
-        Ok(statement) => statement,
-        Err(err) => {
-          // drop(self.fluent_tx);
-          panic!("Error in Source PostgreSQL query: {}", err);
-        }
-      };
+    match self.kind {
+      SourceKind::Postgres => {
+        let mut database_client =
+          database_client.ok_or(eyre!("Source requires a database client"))?;
+        let qp = self.query_params
+                     .ok_or(eyre!("Source requires query_params"))?;
 
-    info!("SQL statement prepared");
+        let query_statement_raw = match rp.mode {
+          RunMode::Replay => format!(include_str!("./sql/source.sql"),
+                                     key_name = qp.key_name,
+                                     timestamp_name = qp.timestamp_name,
+                                     fluent_names = self.publishes.join(", "),
+                                     from_table = qp.from_table,
+                                     start_time = start_time,
+                                     end_time = end_time,
+                                     order_by = qp.order_by),
+          RunMode::Live => format!(include_str!("./sql/source_live.sql"),
+                                   key_name = qp.key_name,
+                                   timestamp_name = qp.timestamp_name,
+                                   fluent_names = self.publishes.join(", "),
+                                   from_table = qp.from_table,
+                                   order_by = qp.order_by,
+                                   fetch_size = qp.fetch_size),
+        };
 
-    let mut interval =
-      time::interval(time::Duration::from_millis(rp.millis_per_cycle));
+        let query_statement =
+          match database_client.prepare(&query_statement_raw).await {
+            Ok(statement) => statement,
+            Err(err) => {
+              // drop(self.fluent_tx);
+              panic!("Error in Source PostgreSQL query: {}", err);
+            }
+          };
 
-    let mut time = start_time;
+        info!("SQL statement prepared");
 
-    // get all data points
-    if let Ok(mut all_rows) =
-      database_client.query(&query_statement, &[]).await
-    {
-      eprintln!("found {} rows between {} and {}",
-                all_rows.len(),
-                start_time,
-                end_time);
-
-      let pb = ProgressBar::new(all_rows.len() as u64);
-      pb.set_style(
-        ProgressStyle::with_template(
-          "[{elapsed_precise}] [{bar:80.cyan/blue}] {pos:>7}/{len:7} {percent:>3}% ({eta_precise})")
-        .unwrap()
-        .progress_chars("#|-"));
-
-      let mut processed = 0;
-
-      // walk through data points, taking only those relevant to the current
-      // time value, which advances by one each iteration
-      while !all_rows.is_empty() {
-        let rows = all_rows.iter()
-                           .rev()
-                           .take_while(|row| {
-                             row.get::<&str, i64>("timestamp") as usize <= time
-                           })
-                           .collect::<Vec<_>>();
-
-        // if no data points for this time, continue
-        if rows.is_empty() {
-          time += 1;
-          interval.tick().await;
-          continue;
+        // make sure the columns hold what the config declares them to hold
+        for column in query_statement.columns() {
+          if let Some((name, column_type)) =
+            columns.iter().find(|(name, _)| name == column.name())
+          {
+            if !column_type.accepts(column.type_()) {
+              bail!("Source column '{}' is of type {}, which cannot be read \
+                     as {:?}",
+                    name,
+                    column.type_(),
+                    column_type);
+            }
+          }
         }
 
-        // store number of rows in var for later use
-        let no_of_rows = rows.len();
-
-        // print number of rows --> "simultaneous data points"
-        println!("{},data_points,{}", time, no_of_rows);
-
-        // process all current timestamps
-        for row in rows {
-          let key = row.get::<&str, i32>("key") as usize;
-          let timestamp = row.get::<&str, i64>("timestamp") as usize;
+        if rp.mode == RunMode::Live {
+          let at_statement_raw =
+            format!(include_str!("./sql/source_live_at.sql"),
+                    key_name = qp.key_name,
+                    timestamp_name = qp.timestamp_name,
+                    fluent_names = self.publishes.join(", "),
+                    from_table = qp.from_table,
+                    order_by = qp.order_by);
+          let at_statement =
+            database_client.prepare(&at_statement_raw).await?;
 
-          node_tx.send(Fluent::new("instant",
-                                   &[key],
-                                   timestamp,
-                                   Box::new(Instant::now())))?;
-
-          for fluent_name in self.publishes.iter() {
-            let value: f64 = row.get(fluent_name.as_str());
-            let fluent =
-              Fluent::new(fluent_name, &[key], timestamp, Box::new(value));
-
-            node_tx.send(fluent)?;
-          }
+          info!("tailing {} for new rows", qp.from_table);
+          return tail(&database_client,
+                      &query_statement,
+                      &at_statement,
+                      qp.fetch_size,
+                      &columns,
+                      &rp,
+                      &node_tx).await;
         }
 
-        // increase time by one to step forward
-        time += 1;
+        // rows are fetched through a portal (i.e. a server-side cursor) in
+        // pages of `fetch_size` rows, which requires a transaction to live in.
+        let transaction = database_client.transaction().await?;
+        let portal = transaction.bind(&query_statement, &[]).await?;
 
-        // drop rows which have been processed
-        all_rows.truncate(all_rows.len().saturating_sub(no_of_rows));
-        processed += no_of_rows;
+        info!("portal bound, fetching pages of {} rows", qp.fetch_size);
 
-        pb.set_position(processed as u64);
+        let records =
+          database_records(&transaction, portal, qp.fetch_size, &columns);
+        publish(records, &columns, &rp, &node_tx).await?;
 
-        // tick the interval
-        interval.tick().await;
+        transaction.commit().await?;
       }
+      SourceKind::Csv | SourceKind::JsonLines => {
+        let fp = self.file_params
+                     .ok_or(eyre!("Source requires file_params"))?;
 
-      pb.finish_with_message("ran all data points");
+        let records = file_records(&fp, &self.kind, &columns).await?;
+        publish(records, &columns, &rp, &node_tx).await?;
+      }
     }
 
     info!("ran requested timeframe ({} hours) or reached end of data",
@@ -164,6 +246,11 @@ impl Source {
 }
 
 impl Node for Source {
+  /// There is only one `Source`.
+  fn name(&self) -> String {
+    "source".to_owned()
+  }
+
   /// `Source` publishes fluents specified by name in the app configuration.
   fn publishes(&self) -> Vec<String> {
     let mut publishes = self.publishes.clone();
@@ -183,14 +270,521 @@ impl Node for Source {
 }
 
 
+/// A single data point read by the [`Source`], holding one value for each of
+/// the published fluents, in the order they are given in.
+struct Record {
+  key:       Key,
+  timestamp: Timestamp,
+  values:    Vec<Box<dyn ValueType>>,
+}
+
+impl Record {
+  /// Reads a [`Record`] from a row retrieved from the database.
+  fn from_row(row: &Row, columns: &[(String, ColumnType)]) -> Result<Self> {
+    let key = row.try_get::<&str, Key>("key")?;
+    let timestamp = row.try_get::<&str, i64>("timestamp")? as Timestamp;
+
+    let mut values = Vec::new();
+    for (name, column_type) in columns {
+      values.push(column_type.read_row(row, name)?);
+    }
+
+    Ok(Self { key,
+              timestamp,
+              values })
+  }
+
+  /// Reads a [`Record`] from a CSV line, using the column positions given by
+  /// the header line of the file.
+  fn from_csv(line: &str,
+              positions: &HashMap<String, usize>,
+              fp: &FileParams,
+              columns: &[(String, ColumnType)])
+              -> Result<Self> {
+    let fields = csv_fields(line)?;
+    let field = |name: &str| -> Result<&str> {
+      positions.get(name)
+               .and_then(|&i| fields.get(i))
+               .map(|field| field.trim())
+               .ok_or(eyre!("CSV line is missing column '{}'", name))
+    };
+
+    let key = field(&fp.key_name)?.parse::<Key>()?;
+    let timestamp = field(&fp.timestamp_name)?.parse::<Timestamp>()?;
+
+    let mut values = Vec::new();
+    for (name, column_type) in columns {
+      values.push(column_type.parse(name, field(name)?)?);
+    }
+
+    Ok(Self { key,
+              timestamp,
+              values })
+  }
+
+  /// Reads a [`Record`] from a line containing a JSON object.
+  fn from_json(line: &str,
+               fp: &FileParams,
+               columns: &[(String, ColumnType)])
+               -> Result<Self> {
+    let object: Map<String, Value> = serde_json::from_str(line)?;
+    let field = |name: &str| -> Result<&Value> {
+      object.get(name)
+            .ok_or(eyre!("JSON line is missing field '{}'", name))
+    };
+
+    // strings are read like CSV fields, so the same id yields the same key
+    let key = match field(&fp.key_name)? {
+      Value::String(key) => key.parse::<Key>()?,
+      key => Key::from(key.as_i64()
+                          .ok_or(eyre!("key is not an integer or string"))?),
+    };
+    let timestamp =
+      field(&fp.timestamp_name)?.as_u64()
+                                .ok_or(eyre!("timestamp is not an integer"))?
+      as Timestamp;
+
+    let mut values = Vec::new();
+    for (name, column_type) in columns {
+      values.push(column_type.read_json(name, field(name)?)?);
+    }
+
+    Ok(Self { key,
+              timestamp,
+              values })
+  }
+
+  /// Sends the `instant` fluent and one fluent per published value.
+  async fn send(self,
+                columns: &[(String, ColumnType)],
+                node_tx: &NodeTx)
+                -> Result<()> {
+    let keys = &[self.key];
+
+    node_tx.send(Fluent::new("instant",
+                             keys,
+                             self.timestamp,
+                             Box::new(Instant::now())))
+           .await?;
+
+    for ((name, _), value) in columns.iter().zip(self.values) {
+      node_tx.send(Fluent::new(name, keys, self.timestamp, value))
+             .await?;
+    }
+    Ok(())
+  }
+}
+
+
+/// Splits a single CSV line into its fields.
+fn csv_fields(line: &str) -> Result<csv::StringRecord> {
+  csv::ReaderBuilder::new().has_headers(false)
+                           .from_reader(line.as_bytes())
+                           .records()
+                           .next()
+                           .ok_or(eyre!("empty CSV line"))?
+                           .map_err(Report::from)
+}
+
+
+/// Creates a stream of [`Record`]s from the database by fetching pages of
+/// `fetch_size` rows from the portal as the stream is consumed.
+fn database_records<'a>(transaction: &'a Transaction<'_>,
+                        portal: Portal,
+                        fetch_size: i32,
+                        columns: &'a [(String, ColumnType)])
+                        -> BoxStream<'a, Result<Record>> {
+  stream::try_unfold((portal, false), move |(portal, exhausted)| async move {
+    if exhausted {
+      return Ok::<_, Report>(None);
+    }
+    let page = transaction.query_portal(&portal, fetch_size).await?;
+    let exhausted = page.len() < fetch_size as usize;
+    Ok(Some((page, (portal, exhausted))))
+  }).map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+    .try_flatten()
+    .and_then(move |row| future::ready(Record::from_row(&row, columns)))
+    .boxed()
+}
+
+
+/// Creates a stream of [`Record`]s from a CSV or JSON lines file by reading
+/// the file line by line as the stream is consumed.
+async fn file_records<'a>(fp: &'a FileParams,
+                          kind: &'a SourceKind,
+                          columns: &'a [(String, ColumnType)])
+                          -> Result<BoxStream<'a, Result<Record>>> {
+  let mut lines = BufReader::new(File::open(&fp.path).await?).lines();
+
+  // CSV files are expected to have a header line naming the columns
+  let mut positions = HashMap::new();
+  if kind == &SourceKind::Csv {
+    let header = lines.next_line()
+                      .await?
+                      .ok_or(eyre!("CSV file '{}' is empty", fp.path))?;
+    for (i, name) in csv_fields(&header)?.iter().enumerate() {
+      positions.insert(name.trim().to_owned(), i);
+    }
+  }
+
+  let records =
+    stream::try_unfold(lines, |mut lines| async move {
+      Ok::<_, Report>(lines.next_line().await?.map(|line| (line, lines)))
+    }).try_filter(|line| future::ready(!line.trim().is_empty()))
+      .and_then(move |line| {
+        future::ready(match kind {
+                        SourceKind::Csv => {
+                          Record::from_csv(&line, &positions, fp, columns)
+                        }
+                        _ => Record::from_json(&line, fp, columns),
+                      })
+      })
+      .boxed();
+
+  Ok(records)
+}
+
+
+/// Publishes [`Record`]s from the stream, advancing time as paced by the
+/// [`ReplayClock`] and sending all records up to the current time each step.
+async fn publish(records: BoxStream<'_, Result<Record>>,
+                 columns: &[(String, ColumnType)],
+                 rp: &RunParams,
+                 node_tx: &NodeTx)
+                 -> Result<()> {
+  let (start_time, end_time) = rp.window();
+
+  let mut records =
+    records.try_skip_while(|r| future::ready(Ok(r.timestamp < start_time)))
+           .try_take_while(|r| future::ready(Ok(r.timestamp <= end_time)))
+           .boxed()
+           .peekable();
+
+  let mut clock = ReplayClock::new(rp.clock.clone(),
+                                   rp.millis_per_cycle,
+                                   rp.timestamp_unit,
+                                   start_time);
+
+  let mut time = start_time;
+
+  let pb = ProgressBar::new(end_time.saturating_sub(start_time) as u64);
+  pb.set_style(
+    ProgressStyle::with_template(
+      "[{elapsed_precise}] [{bar:80.cyan/blue}] {pos:>7}/{len:7} {percent:>3}% ({eta_precise})")
+    .unwrap()
+    .progress_chars("#|-"));
+
+  // walk through data points, taking only those relevant to the current time
+  // value, which advances with the clock each iteration
+  loop {
+    let is_due =
+      |r: &Result<Record>| !matches!(r, Ok(r) if r.timestamp > time);
+
+    let mut no_of_rows = 0;
+    while let Some(record) = Pin::new(&mut records).next_if(is_due).await {
+      record?.send(columns, node_tx).await?;
+      no_of_rows += 1;
+    }
+
+    // log number of rows --> "simultaneous data points"
+    if no_of_rows > 0 {
+      debug!("{},data_points,{}", time, no_of_rows);
+    }
+
+    // stop once all records have been published, otherwise let the clock
+    // step forward towards the next one
+    let next = match Pin::new(&mut records).peek().await {
+      Some(Ok(record)) => record.timestamp,
+      Some(Err(_)) => time,
+      None => break,
+    };
+
+    time = clock.advance(time, next).await;
+    pb.set_position((time - start_time) as u64);
+  }
+
+  pb.finish_with_message("ran all data points");
+  Ok(())
+}
+
+
+/// Polls the database for rows newer than the latest one seen so far and
+/// publishes them right away. Starts with the rows from the start of the run
+/// window onwards and never returns unless an error occurs.
+async fn tail(database_client: &Client,
+              query_statement: &Statement,
+              at_statement: &Statement,
+              fetch_size: i32,
+              columns: &[(String, ColumnType)],
+              rp: &RunParams,
+              node_tx: &NodeTx)
+              -> Result<()> {
+  let (start_time, _) = rp.window();
+  let mut cursor = TailCursor::new(start_time, fetch_size as usize);
+
+  let mut interval =
+    time::interval(time::Duration::from_millis(rp.millis_per_cycle));
+
+  loop {
+    interval.tick().await;
+
+    // a full page means there is a backlog, which is worked off without
+    // waiting for the next cycle
+    let mut backlog = true;
+    while backlog {
+      let rows = match cursor.query() {
+        TailQuery::After(timestamp) => {
+          database_client.query(query_statement, &[&timestamp])
+                         .await?
+        }
+        TailQuery::At(timestamp) => {
+          database_client.query(at_statement, &[&timestamp]).await?
+        }
+      };
+      let records = rows.iter()
+                        .map(|row| Record::from_row(row, columns))
+                        .collect::<Result<Vec<_>>>()?;
+
+      let (records, full_page) = cursor.advance(records);
+      backlog = full_page;
+      if let Some(record) = records.last() {
+        debug!("{} data points up to {}", records.len(), record.timestamp);
+      }
+
+      for record in records {
+        record.send(columns, node_tx).await?;
+      }
+    }
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Rows to fetch next while tailing the database.
+enum TailQuery {
+  /// A page of the rows newer than the given timestamp.
+  After(i64),
+  /// All rows with the given timestamp, which did not fit on one page.
+  At(i64),
+}
+
+
+#[derive(Debug)]
+/// Keeps track of how far [`tail`] has read, such that no rows are skipped
+/// when reading pages of `fetch_size` rows ordered by timestamp.
+struct TailCursor {
+  fetch_size: usize,
+  last_seen:  i64,
+  overflown:  Option<i64>,
+}
+
+impl TailCursor {
+  fn new(start_time: Timestamp, fetch_size: usize) -> Self {
+    Self { fetch_size,
+           last_seen: start_time as i64 - 1,
+           overflown: None }
+  }
+
+  /// Returns which rows to fetch next.
+  fn query(&self) -> TailQuery {
+    match self.overflown {
+      Some(timestamp) => TailQuery::At(timestamp),
+      None => TailQuery::After(self.last_seen),
+    }
+  }
+
+  /// Takes the [`Record`]s fetched for the last query and returns those to
+  /// publish, and whether to fetch again right away.
+  fn advance(&mut self, mut records: Vec<Record>) -> (Vec<Record>, bool) {
+    if let Some(timestamp) = self.overflown.take() {
+      self.last_seen = timestamp;
+      return (records, true);
+    }
+
+    let full_page = records.len() >= self.fetch_size;
+    if full_page {
+      // a timestamp filling a whole page is fetched in one go instead
+      if let (Some(first), Some(last)) = (records.first(), records.last()) {
+        if first.timestamp == last.timestamp {
+          self.overflown = Some(last.timestamp as i64);
+          return (Vec::new(), true);
+        }
+      }
+      retain_complete_timestamps(&mut records);
+    }
+
+    if let Some(record) = records.last() {
+      self.last_seen = record.timestamp as i64;
+    }
+    (records, full_page)
+  }
+}
+
+
+/// Drops the [`Record`]s sharing the latest timestamp from a full page, since
+/// more rows with that timestamp may be on the next page. If all records on
+/// the page share one timestamp, they are kept as they are.
+fn retain_complete_timestamps(records: &mut Vec<Record>) {
+  if let Some(latest) = records.last().map(|record| record.timestamp) {
+    let complete = records.iter()
+                          .take_while(|record| record.timestamp < latest)
+                          .count();
+    if complete > 0 {
+      records.truncate(complete);
+    }
+  }
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Selects where the [`Source`] reads its data from.
+enum SourceKind {
+  #[default]
+  Postgres,
+  Csv,
+  JsonLines,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Selects whether the [`Source`] replays a fixed time window or keeps
+/// following a table which is still being written to.
+enum RunMode {
+  #[default]
+  Replay,
+  Live,
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Declares the type of a column read by the [`Source`]. Each type maps onto
+/// the [`Fluent`] variant of the same name.
+enum ColumnType {
+  Integer,
+  LongInt,
+  #[default]
+  FloatPt,
+  Boolean,
+  Textual,
+  PlanePt,
+}
+
+impl ColumnType {
+  /// Returns `true` if a database column of type `ty` can be read as this
+  /// column type.
+  fn accepts(&self, ty: &Type) -> bool {
+    match self {
+      Self::Integer => <i32 as FromSql>::accepts(ty),
+      Self::LongInt => <i64 as FromSql>::accepts(ty),
+      Self::FloatPt => <f64 as FromSql>::accepts(ty),
+      Self::Boolean => <bool as FromSql>::accepts(ty),
+      Self::Textual => <String as FromSql>::accepts(ty),
+      Self::PlanePt => <PointColumn as FromSql>::accepts(ty),
+    }
+  }
+
+  /// Reads the value of column `name` from a row retrieved from the database.
+  fn read_row(&self, row: &Row, name: &str) -> Result<Box<dyn ValueType>> {
+    Ok(match self {
+         Self::Integer => Box::new(row.try_get::<&str, i32>(name)?),
+         Self::LongInt => Box::new(row.try_get::<&str, i64>(name)?),
+         Self::FloatPt => Box::new(row.try_get::<&str, f64>(name)?),
+         Self::Boolean => Box::new(row.try_get::<&str, bool>(name)?),
+         Self::Textual => Box::new(row.try_get::<&str, String>(name)?),
+         Self::PlanePt => Box::new(row.try_get::<&str, PointColumn>(name)?.0),
+       })
+  }
+
+  /// Parses the value of column `name` from a CSV field. Points are expected
+  /// in the PostgreSQL text format, i.e. `(x,y)`.
+  fn parse(&self, name: &str, field: &str) -> Result<Box<dyn ValueType>> {
+    let invalid =
+      || eyre!("value '{}' of column '{}' is not {:?}", field, name, self);
+    Ok(match self {
+         Self::Integer => {
+           Box::new(field.parse::<i32>().map_err(|_| invalid())?)
+         }
+         Self::LongInt => {
+           Box::new(field.parse::<i64>().map_err(|_| invalid())?)
+         }
+         Self::FloatPt => {
+           Box::new(field.parse::<f64>().map_err(|_| invalid())?)
+         }
+         Self::Boolean => {
+           Box::new(field.parse::<bool>().map_err(|_| invalid())?)
+         }
+         Self::Textual => Box::new(field.to_owned()),
+         Self::PlanePt => {
+           let (x, y) = field.trim_start_matches('(')
+                             .trim_end_matches(')')
+                             .split_once(',')
+                             .ok_or_else(invalid)?;
+           let x = x.trim().parse::<f64>().map_err(|_| invalid())?;
+           let y = y.trim().parse::<f64>().map_err(|_| invalid())?;
+           Box::new((x, y))
+         }
+       })
+  }
+
+  /// Reads the value of field `name` from a JSON value. Points are expected
+  /// as arrays of two numbers, i.e. `[x, y]`.
+  fn read_json(&self,
+               name: &str,
+               value: &Value)
+               -> Result<Box<dyn ValueType>> {
+    let invalid =
+      || eyre!("value {} of field '{}' is not {:?}", value, name, self);
+    Ok(match self {
+         Self::Integer => {
+           let value = value.as_i64().ok_or_else(invalid)?;
+           Box::new(i32::try_from(value).map_err(|_| invalid())?)
+         }
+         Self::LongInt => Box::new(value.as_i64().ok_or_else(invalid)?),
+         Self::FloatPt => Box::new(value.as_f64().ok_or_else(invalid)?),
+         Self::Boolean => Box::new(value.as_bool().ok_or_else(invalid)?),
+         Self::Textual => {
+           Box::new(value.as_str().ok_or_else(invalid)?.to_owned())
+         }
+         Self::PlanePt => match value.as_array().map(|a| a.as_slice()) {
+           Some([x, y]) => Box::new((x.as_f64().ok_or_else(invalid)?,
+                                     y.as_f64().ok_or_else(invalid)?)),
+           _ => bail!(invalid()),
+         },
+       })
+  }
+}
+
+
 #[derive(Clone, Debug, PartialEq, Deserialize)]
 /// Holds parameters for the execution of the [`Source`] service.
 struct RunParams {
+  #[serde(default)]
+  pub mode:             RunMode,
   pub big_bang:         usize,
   pub armageddon:       usize,
   pub starting_offset:  usize,
   pub millis_per_cycle: u64,
   pub hours_to_run:     usize,
+  #[serde(default)]
+  pub timestamp_unit:   TimeUnit,
+  #[serde(default)]
+  pub clock:            ClockMode,
+}
+
+impl RunParams {
+  /// Returns the start and end time of the run. All parameters except for
+  /// `hours_to_run` are given in the `timestamp_unit` of the data.
+  fn window(&self) -> (Timestamp, Timestamp) {
+    let start_time = self.big_bang + self.starting_offset;
+    let run_time =
+      self.hours_to_run * 3_600 * self.timestamp_unit.per_second();
+    let end_time = std::cmp::min(start_time + run_time, self.armageddon);
+
+    (start_time, end_time)
+  }
 }
 
 
@@ -201,6 +795,16 @@ struct QueryParams {
   pub timestamp_name: String,
   pub from_table:     String,
   pub order_by:       String,
+  pub fetch_size:     i32,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// Holds parameters for reading data from a file with the [`Source`].
+struct FileParams {
+  pub path:           String,
+  pub key_name:       String,
+  pub timestamp_name: String,
 }
 
 
@@ -208,10 +812,26 @@ struct QueryParams {
 
 #[cfg(test)]
 mod tests {
-  use super::{Node, QueryParams, RunParams, Source};
-  use crate::stringvec;
+  use super::{retain_complete_timestamps,
+              ClockMode,
+              ColumnType,
+              FileParams,
+              Node,
+              QueryParams,
+              Record,
+              RunMode,
+              RunParams,
+              Source,
+              SourceKind,
+              TailCursor,
+              TailQuery};
+  use crate::{fluent::{Fluent, FluentTrait, Key, TimeUnit},
+              stringvec};
 
   use pretty_assertions::assert_eq;
+  use serde_json::json;
+  use std::{collections::HashMap, fs};
+  use tokio::sync::mpsc;
 
 
   fn run_params() -> (usize, usize, usize, u64, usize) {
@@ -231,38 +851,93 @@ mod tests {
          millis_per_cycle,
          hours_to_run) = run_params();
 
-    RunParams { big_bang,
+    RunParams { mode: RunMode::Replay,
+                big_bang,
                 armageddon,
                 starting_offset,
                 millis_per_cycle,
-                hours_to_run }
+                hours_to_run,
+                timestamp_unit: TimeUnit::Seconds,
+                clock: ClockMode::Fixed }
   }
 
-  fn query_params() -> (String, String, String, String) {
+  fn query_params() -> (String, String, String, String, i32) {
     let key_name = String::from("id");
     let timestamp_name = String::from("ts");
     let from_table = String::from("the.matrix");
     let order_by = String::from("serial");
+    let fetch_size = 4_096;
 
-    (key_name, timestamp_name, from_table, order_by)
+    (key_name, timestamp_name, from_table, order_by, fetch_size)
   }
 
   fn query_params_init() -> QueryParams {
-    let (key_name, timestamp_name, from_table, order_by) = query_params();
+    let (key_name, timestamp_name, from_table, order_by, fetch_size) =
+      query_params();
 
     QueryParams { key_name,
                   timestamp_name,
                   from_table,
-                  order_by }
+                  order_by,
+                  fetch_size }
   }
 
   fn source_init(rp: &RunParams, qp: &QueryParams) -> Source {
     Source { publishes:    stringvec!["lon", "lat", "speed"],
+             column_types: HashMap::new(),
+             kind:         SourceKind::Postgres,
              run_params:   rp.clone(),
-             query_params: qp.clone(),
+             query_params: Some(qp.clone()),
+             file_params:  None,
              node_tx:      None, }
   }
 
+  fn file_source_init(kind: SourceKind, path: &str) -> Source {
+    let run_params = RunParams { mode:             RunMode::Replay,
+                                 big_bang:         1000,
+                                 armageddon:       2000,
+                                 starting_offset:  10,
+                                 millis_per_cycle: 1,
+                                 hours_to_run:     1,
+                                 timestamp_unit:   TimeUnit::Seconds,
+                                 clock:            ClockMode::SkipGaps, };
+    let file_params = FileParams { path:           path.to_owned(),
+                                   key_name:       String::from("id"),
+                                   timestamp_name: String::from("ts"), };
+
+    Source { publishes: stringvec!["lon", "lat"],
+             column_types: HashMap::new(),
+             kind,
+             run_params,
+             query_params: None,
+             file_params: Some(file_params),
+             node_tx: None }
+  }
+
+  async fn run_file_source(kind: SourceKind,
+                           file_name: &str,
+                           contents: &str)
+                           -> Vec<(String, Vec<Key>, usize)> {
+    let path = std::env::temp_dir().join(file_name);
+    fs::write(&path, contents).unwrap();
+
+    let mut source = file_source_init(kind, path.to_str().unwrap());
+    assert!(!source.requires_database());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    source.initialize(tx.into(), Default::default());
+    source.run(None).await.unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut received = Vec::new();
+    while let Ok(fluent) = rx.try_recv() {
+      received.push((fluent.name().to_owned(),
+                     fluent.keys().to_vec(),
+                     fluent.timestamp()));
+    }
+    received
+  }
+
   #[test]
   fn source_test() {
     let (big_bang,
@@ -278,21 +953,224 @@ mod tests {
     assert_eq!(rp.millis_per_cycle, millis_per_cycle);
     assert_eq!(rp.hours_to_run, hours_to_run);
 
-    let (key_name, timestamp_name, from_table, order_by) = query_params();
+    let (key_name, timestamp_name, from_table, order_by, fetch_size) =
+      query_params();
 
     let qp = query_params_init();
     assert_eq!(qp.key_name, key_name);
     assert_eq!(qp.timestamp_name, timestamp_name);
     assert_eq!(qp.from_table, from_table);
     assert_eq!(qp.order_by, order_by);
+    assert_eq!(qp.fetch_size, fetch_size);
 
     let src = source_init(&rp, &qp);
     assert_eq!(src.publishes, stringvec!["lon", "lat", "speed"]);
     assert_eq!(src.publishes(),
                stringvec!["lon", "lat", "speed", "instant"]);
     assert_eq!(src.subscribes_to(), Vec::<String>::new());
+    assert_eq!(src.kind, SourceKind::Postgres);
+    assert!(src.requires_database());
+    assert!(!src.is_live());
     assert_eq!(src.run_params, rp);
-    assert_eq!(src.query_params, qp);
+    assert_eq!(src.query_params, Some(qp));
+    assert_eq!(src.file_params, None);
     assert!(src.node_tx.is_none());
   }
+
+  #[tokio::test]
+  async fn csv_source_test() {
+    let contents = "id,ts,lon,lat\n1,1005,-4.5,48.3\n1,1010,-4.6,48.4\n2,\
+                    1012,-4.7,48.5\n2,5000,-4.8,48.6\n";
+    let received = run_file_source(SourceKind::Csv,
+                                   "magritte_source_test.csv",
+                                   contents).await;
+
+    // the first row is before and the last one after the run window
+    assert_eq!(received,
+               vec![(String::from("instant"), vec![Key::from(1)], 1010),
+                    (String::from("lon"), vec![Key::from(1)], 1010),
+                    (String::from("lat"), vec![Key::from(1)], 1010),
+                    (String::from("instant"), vec![Key::from(2)], 1012),
+                    (String::from("lon"), vec![Key::from(2)], 1012),
+                    (String::from("lat"), vec![Key::from(2)], 1012)]);
+  }
+
+  #[tokio::test]
+  async fn json_lines_source_test() {
+    let contents = r#"{"id": 7, "ts": 1010, "lon": -4.5, "lat": 48.3}
+
+                      {"id": "IMO9241061", "ts": 1011, "lon": -4.6, "lat": 48.4}
+                      {"id": "7", "ts": 1012, "lon": -4.7, "lat": 48.5}"#;
+    let received = run_file_source(SourceKind::JsonLines,
+                                   "magritte_source_test.jsonl",
+                                   contents).await;
+
+    let imo = vec![Key::from("IMO9241061")];
+    assert_eq!(received,
+               vec![(String::from("instant"), vec![Key::from(7)], 1010),
+                    (String::from("lon"), vec![Key::from(7)], 1010),
+                    (String::from("lat"), vec![Key::from(7)], 1010),
+                    (String::from("instant"), imo.clone(), 1011),
+                    (String::from("lon"), imo.clone(), 1011),
+                    (String::from("lat"), imo, 1011),
+                    (String::from("instant"), vec![Key::from(7)], 1012),
+                    (String::from("lon"), vec![Key::from(7)], 1012),
+                    (String::from("lat"), vec![Key::from(7)], 1012)]);
+  }
+
+  #[test]
+  fn window_test() {
+    let mut rp = run_params_init();
+    rp.armageddon = 1_000_000_000;
+    assert_eq!(rp.window(), (1972 + 1337, 1972 + 1337 + 23 * 3_600));
+
+    rp.timestamp_unit = TimeUnit::Milliseconds;
+    assert_eq!(rp.window(), (1972 + 1337, 1972 + 1337 + 23 * 3_600_000));
+
+    rp.armageddon = 2042;
+    assert_eq!(rp.window(), (1972 + 1337, 2042));
+  }
+
+  #[test]
+  fn columns_test() {
+    let mut src = source_init(&run_params_init(), &query_params_init());
+    src.column_types
+       .insert(String::from("speed"), ColumnType::Integer);
+
+    assert_eq!(src.columns().unwrap(),
+               vec![(String::from("lon"), ColumnType::FloatPt),
+                    (String::from("lat"), ColumnType::FloatPt),
+                    (String::from("speed"), ColumnType::Integer)]);
+
+    src.column_types
+       .insert(String::from("heading"), ColumnType::Integer);
+    assert!(src.columns().is_err());
+  }
+
+  #[test]
+  fn validate_test() {
+    let mut qp = query_params_init();
+    assert!(source_init(&run_params_init(), &qp).validate().is_ok());
+
+    qp.fetch_size = 0;
+    assert!(source_init(&run_params_init(), &qp).validate().is_err());
+
+    let mut rp = run_params_init();
+    let qp = query_params_init();
+    for speed_up in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+      rp.clock = ClockMode::RealTime { speed_up };
+      let err = source_init(&rp, &qp).validate().unwrap_err();
+      assert_eq!(err.to_string(),
+                 format!("clock speed_up must be positive and finite, is {}",
+                         speed_up));
+    }
+    rp.clock = ClockMode::RealTime { speed_up: 10.0 };
+    assert!(source_init(&rp, &qp).validate().is_ok());
+  }
+
+  #[test]
+  fn column_type_test() {
+    let fluent = |column_type: ColumnType, field: &str| {
+      Fluent::new("f",
+                  &[Key::from(1)],
+                  2,
+                  column_type.parse("f", field).unwrap())
+    };
+
+    assert_eq!(fluent(ColumnType::Integer, "42").value::<i32>(), 42);
+    assert_eq!(fluent(ColumnType::LongInt, "42").value::<i64>(), 42);
+    assert_eq!(fluent(ColumnType::FloatPt, "4.2").value::<f64>(), 4.2);
+    assert!(fluent(ColumnType::Boolean, "true").value::<bool>());
+    assert_eq!(fluent(ColumnType::Textual, "tug").value::<String>(), "tug");
+    let point = fluent(ColumnType::PlanePt, "(-4.5,48.3)");
+    assert_eq!(point.value::<(f64, f64)>(), (-4.5, 48.3));
+    assert!(ColumnType::Integer.parse("f", "4.2").is_err());
+    assert!(ColumnType::Boolean.parse("f", "1").is_err());
+    assert!(ColumnType::PlanePt.parse("f", "-4.5").is_err());
+
+    let fluent = |column_type: ColumnType, value| {
+      Fluent::new("f",
+                  &[Key::from(1)],
+                  2,
+                  column_type.read_json("f", &value).unwrap())
+    };
+
+    assert_eq!(fluent(ColumnType::Integer, json!(42)).value::<i32>(), 42);
+    assert_eq!(fluent(ColumnType::LongInt, json!(42)).value::<i64>(), 42);
+    assert_eq!(fluent(ColumnType::FloatPt, json!(4.2)).value::<f64>(), 4.2);
+    assert!(fluent(ColumnType::Boolean, json!(true)).value::<bool>());
+    assert_eq!(fluent(ColumnType::Textual, json!("tug")).value::<String>(),
+               "tug");
+    let point = fluent(ColumnType::PlanePt, json!([-4.5, 48.3]));
+    assert_eq!(point.value::<(f64, f64)>(), (-4.5, 48.3));
+    assert!(ColumnType::Integer.read_json("f", &json!(1u64 << 40))
+                               .is_err());
+    assert!(ColumnType::Textual.read_json("f", &json!(42)).is_err());
+  }
+
+  #[tokio::test]
+  async fn live_file_source_test() {
+    let mut source = file_source_init(SourceKind::Csv, "nonexistent.csv");
+    source.run_params.mode = RunMode::Live;
+    assert!(source.is_live());
+
+    let (tx, _rx) = mpsc::unbounded_channel();
+    source.initialize(tx.into(), Default::default());
+    assert!(source.run(None).await.is_err());
+  }
+
+  fn records(timestamps: &[usize]) -> Vec<Record> {
+    timestamps.iter()
+              .map(|&timestamp| Record { key: Key::from(1),
+                                         timestamp,
+                                         values: Vec::new() })
+              .collect()
+  }
+
+  fn timestamps(records: &[Record]) -> Vec<usize> {
+    records.iter().map(|r| r.timestamp).collect()
+  }
+
+  #[test]
+  fn retain_complete_timestamps_test() {
+    let mut page = records(&[1, 2, 2, 3, 3]);
+    retain_complete_timestamps(&mut page);
+    assert_eq!(timestamps(&page), vec![1, 2, 2]);
+
+    let mut page = records(&[3, 3, 3]);
+    retain_complete_timestamps(&mut page);
+    assert_eq!(timestamps(&page), vec![3, 3, 3]);
+
+    let mut page = records(&[]);
+    retain_complete_timestamps(&mut page);
+    assert!(page.is_empty());
+  }
+  #[test]
+  fn tail_cursor_test() {
+    // more rows share the timestamp 3 than fit on a page of 2
+    let table = [1, 3, 3, 3, 4];
+    let mut cursor = TailCursor::new(0, 2);
+    let mut published = Vec::new();
+    let mut backlog = true;
+    while backlog {
+      let fetched = match cursor.query() {
+        TailQuery::After(after) => {
+          table.iter()
+               .filter(|&&timestamp| timestamp as i64 > after)
+               .take(2)
+               .copied()
+               .collect::<Vec<_>>()
+        }
+        TailQuery::At(at) => table.iter()
+                                  .filter(|&&timestamp| timestamp as i64 == at)
+                                  .copied()
+                                  .collect(),
+      };
+      let (records, full_page) = cursor.advance(records(&fetched));
+      published.extend(timestamps(&records));
+      backlog = full_page;
+    }
+    assert_eq!(published, vec![1, 3, 3, 3, 4]);
+    assert_eq!(cursor.query(), TailQuery::After(4));
+  }
 }
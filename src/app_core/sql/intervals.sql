@@ -0,0 +1,9 @@
+-- copying into intervals table...
+copy magritte.intervals (
+  fluent_name,
+  keys,
+  start_time,
+  end_time
+)
+-- ... from rows sent in the binary format.
+from stdin binary
@@ -2,12 +2,28 @@
 drop schema if exists magritte cascade;
 create schema magritte;
 
--- creating event stream table
+-- creating event stream table; of the value columns, only the one matching
+-- value_type is set
 create table magritte.event_stream (
   id          serial,
   fluent_name text,
-  keys        integer[],
+  keys        text[],
   timestamp   bigint,
-  value       bool,
+  value_type  text,
+  bool_value  bool,
+  int_value   bigint,
+  float_value double precision,
+  text_value  text,
+  point_value point,
   last_change bigint
 );
+
+-- creating intervals table; intervals are [start_time, end_time), without an
+-- end_time if still open when the run ended
+create table magritte.intervals (
+  id          serial,
+  fluent_name text,
+  keys        text[],
+  start_time  bigint,
+  end_time    bigint
+);
@@ -1,13 +1,15 @@
--- inserting into event stream table...
-insert into
-  magritte.event_stream (
-    fluent_name,
-    keys,
-    timestamp,
-    value,
-    last_change
-  )
--- ... using the following variables.
--- variables are replaced by values.
-values
-  ($1, $2, $3, $4, $5)
+-- copying into event stream table...
+copy magritte.event_stream (
+  fluent_name,
+  keys,
+  timestamp,
+  value_type,
+  bool_value,
+  int_value,
+  float_value,
+  text_value,
+  point_value,
+  last_change
+)
+-- ... from rows sent in the binary format.
+from stdin binary
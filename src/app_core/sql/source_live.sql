@@ -0,0 +1,6 @@
+-- parameters are placed in from app config, $1 is the latest timestamp seen
+select {key_name} as "key", {timestamp_name} as "timestamp", {fluent_names}
+from {from_table}
+where {timestamp_name} > $1
+order by {timestamp_name} asc, {order_by} asc
+limit {fetch_size}
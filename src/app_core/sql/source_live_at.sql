@@ -0,0 +1,6 @@
+-- parameters are placed in from app config, $1 is a timestamp with more rows
+-- than fit on one page
+select {key_name} as "key", {timestamp_name} as "timestamp", {fluent_names}
+from {from_table}
+where {timestamp_name} = $1
+order by {order_by} asc
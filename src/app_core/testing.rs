@@ -0,0 +1,65 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::{Database, Node, NodeRx, NodeTx};
+
+use indoc::indoc;
+
+
+#[derive(Debug, Default)]
+/// [`Node`] publishing and subscribing to whatever it is given, which keeps
+/// the channels it is initialized with for the test to use.
+pub struct TestNode {
+  pub name:          &'static str,
+  pub publishes:     Vec<String>,
+  pub subscribes_to: Vec<String>,
+  pub node_ch:       Option<(NodeTx, NodeRx)>,
+}
+
+impl TestNode {
+  pub fn new(name: &'static str,
+             publishes: &[&str],
+             subscribes_to: &[&str])
+             -> Self {
+    let strings =
+      |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+    Self { name,
+           publishes: strings(publishes),
+           subscribes_to: strings(subscribes_to),
+           node_ch: None }
+  }
+}
+
+impl Node for TestNode {
+  fn name(&self) -> String {
+    self.name.to_owned()
+  }
+
+  fn publishes(&self) -> Vec<String> {
+    self.publishes.clone()
+  }
+
+  fn subscribes_to(&self) -> Vec<String> {
+    self.subscribes_to.clone()
+  }
+
+  fn initialize(&mut self, node_tx: NodeTx, node_rx: NodeRx) {
+    self.node_ch = Some((node_tx, node_rx));
+  }
+}
+
+
+/// Returns the settings of a [`Database`] which is never connected to, for
+/// nodes which need one but have no database query.
+pub fn database() -> Database {
+  toml::from_str(indoc! {r#"
+    host = "localhost"
+    user = "postgres"
+    password = "postgres"
+    dbname = "postgres"
+    timeout = 80
+  "#}).unwrap()
+}
@@ -4,7 +4,12 @@
 // received a copy of this license along with the source code. If that is not
 // the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
 
+use super::graph::GraphFormat;
+
+use bytes::{BufMut, BytesMut};
 use clap::Parser;
+use std::error::Error;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
 
 
 #[derive(Debug, Parser)]
@@ -13,7 +18,52 @@ use clap::Parser;
 pub struct CommandLineArgs {
   /// Set path for config file
   #[clap(short, long, default_value = "./conf/app_core.toml")]
-  pub config_path: String,
+  pub config_path:  String,
+  /// Print the graph of fluents passed between source, handlers and sinks in
+  /// the given format instead of running
+  #[clap(long, value_enum)]
+  pub export_graph: Option<GraphFormat>,
+}
+
+
+#[derive(Debug, PartialEq)]
+/// Helper to read and write PostgreSQL `point` columns, for which
+/// `tokio_postgres` provides no conversion without additional features.
+pub struct PointColumn(pub (f64, f64));
+
+impl<'a> FromSql<'a> for PointColumn {
+  fn from_sql(_: &Type,
+              raw: &'a [u8])
+              -> Result<Self, Box<dyn Error + Sync + Send>> {
+    if raw.len() != 16 {
+      return Err("invalid message length for point".into());
+    }
+    let x = f64::from_be_bytes(raw[..8].try_into()?);
+    let y = f64::from_be_bytes(raw[8..].try_into()?);
+    Ok(Self((x, y)))
+  }
+
+  fn accepts(ty: &Type) -> bool {
+    *ty == Type::POINT
+  }
+}
+
+impl ToSql for PointColumn {
+  to_sql_checked!();
+
+  fn to_sql(&self,
+            _: &Type,
+            out: &mut BytesMut)
+            -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+    let (x, y) = self.0;
+    out.put_f64(x);
+    out.put_f64(y);
+    Ok(IsNull::No)
+  }
+
+  fn accepts(ty: &Type) -> bool {
+    *ty == Type::POINT
+  }
 }
 
 
@@ -42,10 +92,12 @@ macro_rules! sqlvec {
 
 #[cfg(test)]
 mod tests {
-  use super::CommandLineArgs;
+  use super::{CommandLineArgs, PointColumn};
 
+  use bytes::BytesMut;
   use clap::Parser;
   use pretty_assertions::assert_eq;
+  use tokio_postgres::types::{FromSql, ToSql, Type};
 
 
   #[test]
@@ -53,4 +105,17 @@ mod tests {
     let cla = CommandLineArgs::parse();
     assert_eq!(cla.config_path, String::from("./conf/app_core.toml"));
   }
+
+  #[test]
+  fn point_column_test() {
+    let mut raw = BytesMut::new();
+    PointColumn((13.37, -4.2)).to_sql(&Type::POINT, &mut raw)
+                              .unwrap();
+    assert_eq!(raw.len(), 16);
+    assert_eq!(PointColumn::from_sql(&Type::POINT, &raw).unwrap(),
+               PointColumn((13.37, -4.2)));
+    assert!(PointColumn::from_sql(&Type::POINT, &raw[..8]).is_err());
+    assert!(<PointColumn as ToSql>::accepts(&Type::POINT));
+    assert!(!<PointColumn as ToSql>::accepts(&Type::FLOAT8));
+  }
 }
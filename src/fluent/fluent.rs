@@ -62,6 +62,20 @@ impl Fluent {
       Self::Instant(fluent) => fluent.update(timestamp, new_fluent.value()),
     }
   }
+
+  /// Keep the timestamps of the changes of the value before the last one, for
+  /// as long as they are no more than `horizon` in the past of the fluent.
+  pub fn keep_changes(&mut self, horizon: Timestamp) {
+    match self {
+      Self::Textual(fluent) => fluent.keep_changes(horizon),
+      Self::Integer(fluent) => fluent.keep_changes(horizon),
+      Self::LongInt(fluent) => fluent.keep_changes(horizon),
+      Self::FloatPt(fluent) => fluent.keep_changes(horizon),
+      Self::Boolean(fluent) => fluent.keep_changes(horizon),
+      Self::PlanePt(fluent) => fluent.keep_changes(horizon),
+      Self::Instant(fluent) => fluent.keep_changes(horizon),
+    }
+  }
 }
 
 impl FluentTrait for Fluent {
@@ -128,13 +142,26 @@ impl FluentTrait for Fluent {
       Self::Instant(fluent) => fluent.last_change(),
     }
   }
+
+  /// Helper function to get the changes of fluent before its last change.
+  fn changes(&self) -> &[Timestamp] {
+    match self {
+      Self::Textual(fluent) => fluent.changes(),
+      Self::Integer(fluent) => fluent.changes(),
+      Self::LongInt(fluent) => fluent.changes(),
+      Self::FloatPt(fluent) => fluent.changes(),
+      Self::Boolean(fluent) => fluent.changes(),
+      Self::PlanePt(fluent) => fluent.changes(),
+      Self::Instant(fluent) => fluent.changes(),
+    }
+  }
 }
 
 // fin --------------------------------------------------------------------- //
 
 #[cfg(test)]
 mod tests {
-  use super::{Fluent, FluentTrait, InnerFluent};
+  use super::{Fluent, FluentTrait, InnerFluent, Key};
 
   use pretty_assertions::assert_eq;
   use std::f64::consts;
@@ -143,7 +170,7 @@ mod tests {
   #[test]
   fn textual_fluent_test() {
     let name = "textual_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = String::from("running");
 
@@ -153,7 +180,9 @@ mod tests {
     assert!(matches!(any_fluent, Fluent::Textual(..)));
     assert_eq!(any_fluent.name(), name);
 
-    let Fluent::Textual(extracted) = any_fluent.clone() else { panic!() };
+    let Fluent::Textual(extracted) = any_fluent.clone() else {
+      panic!()
+    };
     let fluent = InnerFluent::new(name, keys, timestamp, value);
 
     assert_eq!(extracted, fluent);
@@ -166,7 +195,7 @@ mod tests {
   #[test]
   fn integer_fluent_test() {
     let name = "integer_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = 3;
 
@@ -175,7 +204,9 @@ mod tests {
     assert!(matches!(any_fluent, Fluent::Integer(..)));
     assert_eq!(any_fluent.name(), name);
 
-    let Fluent::Integer(extracted) = any_fluent.clone() else { panic!() };
+    let Fluent::Integer(extracted) = any_fluent.clone() else {
+      panic!()
+    };
     let fluent = InnerFluent::new(name, keys, timestamp, value);
 
     assert_eq!(extracted, fluent);
@@ -188,7 +219,7 @@ mod tests {
   #[test]
   fn floatpt_fluent_test() {
     let name = "floatpt_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = consts::PI;
 
@@ -197,7 +228,9 @@ mod tests {
     assert!(matches!(any_fluent, Fluent::FloatPt(..)));
     assert_eq!(any_fluent.name(), name);
 
-    let Fluent::FloatPt(extracted) = any_fluent.clone() else { panic!() };
+    let Fluent::FloatPt(extracted) = any_fluent.clone() else {
+      panic!()
+    };
     let fluent = InnerFluent::new(name, keys, timestamp, value);
 
     assert_eq!(extracted, fluent);
@@ -210,7 +243,7 @@ mod tests {
   #[test]
   fn boolean_fluent_test() {
     let name = "boolean_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = true;
 
@@ -219,7 +252,9 @@ mod tests {
     assert!(matches!(any_fluent, Fluent::Boolean(..)));
     assert_eq!(any_fluent.name(), name);
 
-    let Fluent::Boolean(extracted) = any_fluent.clone() else { panic!() };
+    let Fluent::Boolean(extracted) = any_fluent.clone() else {
+      panic!()
+    };
     let fluent = InnerFluent::new(name, keys, timestamp, value);
 
     assert_eq!(extracted, fluent);
@@ -232,7 +267,7 @@ mod tests {
   #[test]
   fn planept_fluent_test() {
     let name = "planept_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = (consts::PI, consts::E);
 
@@ -241,7 +276,9 @@ mod tests {
     assert!(matches!(any_fluent, Fluent::PlanePt(..)));
     assert_eq!(any_fluent.name(), name);
 
-    let Fluent::PlanePt(extracted) = any_fluent.clone() else { panic!() };
+    let Fluent::PlanePt(extracted) = any_fluent.clone() else {
+      panic!()
+    };
     let fluent = InnerFluent::new(name, keys, timestamp, value);
 
     assert_eq!(extracted, fluent);
@@ -6,10 +6,12 @@
 
 use super::{FluentTrait, Key, Timestamp, ValueType};
 
+use derivative::Derivative;
 use std::cmp::Ordering;
 
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Derivative)]
+#[derivative(Debug)]
 /// Core application data type. Any property that is subject to change is
 /// represented by a fluent.
 pub struct InnerFluent<VT: ValueType + PartialEq + Clone> {
@@ -18,6 +20,10 @@ pub struct InnerFluent<VT: ValueType + PartialEq + Clone> {
   timestamp:   Timestamp,
   value:       VT,
   last_change: Timestamp,
+  #[derivative(Debug = "ignore")]
+  changes:     Vec<Timestamp>,
+  #[derivative(Debug = "ignore")]
+  horizon:     Option<Timestamp>,
 }
 
 impl<VT: ValueType + PartialEq + Clone> InnerFluent<VT> {
@@ -33,7 +39,15 @@ impl<VT: ValueType + PartialEq + Clone> InnerFluent<VT> {
            keys: keys.to_owned(),
            timestamp,
            value,
-           last_change: timestamp }
+           last_change: timestamp,
+           changes: Vec::new(),
+           horizon: None }
+  }
+
+  /// Keep the timestamps of the changes of the value before the last one, for
+  /// as long as they are no more than `horizon` in the past of the fluent.
+  pub fn keep_changes(&mut self, horizon: Timestamp) {
+    self.horizon = Some(horizon);
   }
 
   /// Update the fluent with a new timestamp. Update value if it has
@@ -42,6 +56,19 @@ impl<VT: ValueType + PartialEq + Clone> InnerFluent<VT> {
     self.timestamp = timestamp;
     if self.value != value {
       self.value = value;
+      if let Some(horizon) = self.horizon {
+        self.changes.push(self.last_change);
+        // drop the changes to values which stopped being current before the
+        // horizon, each value being current until the following change
+        let cutoff = timestamp.saturating_sub(horizon);
+        let expired = self.changes
+                          .iter()
+                          .skip(1)
+                          .chain([&timestamp])
+                          .take_while(|&&end| end <= cutoff)
+                          .count();
+        self.changes.drain(..expired);
+      }
       self.last_change = timestamp;
     }
   }
@@ -67,6 +94,10 @@ impl<VT: ValueType + PartialEq + Clone> FluentTrait for InnerFluent<VT> {
   fn last_change(&self) -> Timestamp {
     self.last_change
   }
+
+  fn changes(&self) -> &[Timestamp] {
+    self.changes.as_slice()
+  }
 }
 
 impl<VT: ValueType + PartialEq + Clone> PartialEq for InnerFluent<VT> {
@@ -95,7 +126,7 @@ impl<VT: ValueType + PartialEq + Clone> Ord for InnerFluent<VT> {
 
 #[cfg(test)]
 mod tests {
-  use super::{FluentTrait, InnerFluent};
+  use super::{FluentTrait, InnerFluent, Key};
 
   use pretty_assertions::assert_eq;
   use std::{convert::From, f64::consts};
@@ -104,7 +135,7 @@ mod tests {
   #[test]
   fn textual_fluent_test() {
     let name = "textual_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = String::from("running");
 
@@ -126,7 +157,7 @@ mod tests {
   #[test]
   fn integer_fluent_test() {
     let name = "integer_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = 3;
 
@@ -148,7 +179,7 @@ mod tests {
   #[test]
   fn floatpt_fluent_test() {
     let name = "floatpt_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = consts::PI;
 
@@ -170,7 +201,7 @@ mod tests {
   #[test]
   fn boolean_fluent_test() {
     let name = "boolean_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = true;
 
@@ -189,10 +220,29 @@ mod tests {
     assert_eq!(fluent.last_change(), timestamp);
   }
 
+  #[test]
+  fn changes_test() {
+    let keys = &[Key::from(23)];
+    let mut fluent = InnerFluent::new("boolean_fluent", keys, 100, true);
+    fluent.update(200, false);
+    assert!(fluent.changes().is_empty());
+
+    fluent.keep_changes(300);
+    fluent.update(300, true);
+    fluent.update(350, true);
+    fluent.update(400, false);
+    assert_eq!(fluent.changes(), &[200, 300]);
+    assert_eq!(fluent.last_change(), 400);
+
+    // false from 200 to 300 is no longer within the horizon
+    fluent.update(650, true);
+    assert_eq!(fluent.changes(), &[300, 400]);
+  }
+
   #[test]
   fn planept_fluent_test() {
     let name = "planept_fluent";
-    let keys = &[23, 42];
+    let keys = &[Key::from(23), Key::from(42)];
     let timestamp = 1337;
     let value = (consts::PI, consts::E);
 
@@ -0,0 +1,149 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use std::{error::Error, fmt, str::FromStr};
+use tokio_postgres::types::{FromSql, Type};
+
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Key, i.e. sub-stream identifier, of a fluent. Entities may be identified
+/// by integers (e.g. MMSI numbers) or by strings (e.g. call signs, UUIDs).
+pub enum Key {
+  LongInt(i64),
+  Textual(String),
+}
+
+impl Key {
+  /// Returns the integer value of the [`Key`], if it is one.
+  pub fn as_integer(&self) -> Option<i64> {
+    match self {
+      Self::LongInt(key) => Some(*key),
+      Self::Textual(_) => None,
+    }
+  }
+}
+
+impl From<i64> for Key {
+  fn from(key: i64) -> Self {
+    Self::LongInt(key)
+  }
+}
+
+impl From<&str> for Key {
+  fn from(key: &str) -> Self {
+    Self::Textual(key.to_owned())
+  }
+}
+
+impl From<String> for Key {
+  fn from(key: String) -> Self {
+    Self::Textual(key)
+  }
+}
+
+impl FromStr for Key {
+  type Err = std::convert::Infallible;
+
+  /// Strings which represent an integer as it is written back, e.g. `"42"`
+  /// but neither `"007"` nor `"+42"`, become [`Key::LongInt`], all others
+  /// become [`Key::Textual`], so no key loses characters on the way.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s.parse::<i64>() {
+         Ok(key) if key.to_string() == s => Self::LongInt(key),
+         _ => Self::Textual(s.to_owned()),
+       })
+  }
+}
+
+impl fmt::Display for Key {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::LongInt(key) => write!(f, "{}", key),
+      Self::Textual(key) => write!(f, "{}", key),
+    }
+  }
+}
+
+impl fmt::Debug for Key {
+  /// Keys are printed like the value they hold, to keep debug output of
+  /// fluents concise.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::LongInt(key) => write!(f, "{:?}", key),
+      Self::Textual(key) => write!(f, "{:?}", key),
+    }
+  }
+}
+
+impl<'a> FromSql<'a> for Key {
+  /// Text columns are parsed like keys read from files, see [`Key::from_str`],
+  /// so the same key is equal whichever source it is read from.
+  fn from_sql(ty: &Type,
+              raw: &'a [u8])
+              -> Result<Self, Box<dyn Error + Sync + Send>> {
+    Ok(match *ty {
+         Type::INT2 => Self::LongInt(i16::from_sql(ty, raw)? as i64),
+         Type::INT4 => Self::LongInt(i32::from_sql(ty, raw)? as i64),
+         Type::INT8 => Self::LongInt(i64::from_sql(ty, raw)?),
+         _ => String::from_sql(ty, raw)?.parse()?,
+       })
+  }
+
+  fn accepts(ty: &Type) -> bool {
+    <i16 as FromSql>::accepts(ty)
+    || <i32 as FromSql>::accepts(ty)
+    || <i64 as FromSql>::accepts(ty)
+    || <String as FromSql>::accepts(ty)
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::Key;
+
+  use pretty_assertions::assert_eq;
+  use tokio_postgres::types::{FromSql, Type};
+
+
+  #[test]
+  fn key_test() {
+    let integer = Key::from(226338000);
+    let textual = Key::from("IMO9241061");
+
+    assert_eq!(integer, Key::LongInt(226338000));
+    assert_eq!(textual, Key::Textual(String::from("IMO9241061")));
+    assert_eq!(integer.as_integer(), Some(226338000));
+    assert_eq!(textual.as_integer(), None);
+
+    assert_eq!("226338000".parse::<Key>().unwrap(), integer);
+    assert_eq!("IMO9241061".parse::<Key>().unwrap(), textual);
+    assert_eq!("007".parse::<Key>().unwrap(), Key::from("007"));
+    assert_eq!("+7".parse::<Key>().unwrap(), Key::from("+7"));
+    assert_eq!("-7".parse::<Key>().unwrap(), Key::from(-7));
+
+    assert_eq!(format!("{}", integer), "226338000");
+    assert_eq!(format!("{}", textual), "IMO9241061");
+    assert_eq!(format!("{:?}", vec![integer.clone(), textual.clone()]),
+               r#"[226338000, "IMO9241061"]"#);
+
+    // integer keys sort before textual keys
+    assert!(integer < textual);
+    assert!(Key::from(23) < Key::from(42));
+  }
+
+  #[test]
+  fn from_sql_test() {
+    for key in ["42", "-7", "007", "+7", "IMO9241061"] {
+      let from_sql = Key::from_sql(&Type::TEXT, key.as_bytes()).unwrap();
+      assert_eq!(from_sql, key.parse::<Key>().unwrap());
+    }
+    assert_eq!(Key::from_sql(&Type::VARCHAR, b"42").unwrap(), Key::from(42));
+    assert_eq!(Key::from_sql(&Type::INT8, &42i64.to_be_bytes()).unwrap(),
+               Key::from(42));
+  }
+}
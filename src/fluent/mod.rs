@@ -15,23 +15,47 @@
 //! [`InnerFluent`] and provides some convenience functionality to deal with
 //! [`InnerFluent`]s, and the [`ValueType`] trait which helps [`Fluent`] and
 //! the rest of the application deal with the generic value type of
-//! [`InnerFluent`].
+//! [`InnerFluent`]. Fluents are identified by their name and their [`Key`]s.
 
 mod fluent;
 mod inner_fluent;
+mod key;
 mod value_type;
 
 pub use fluent::Fluent;
 pub use inner_fluent::InnerFluent;
+pub use key::Key;
 pub use value_type::ValueType;
 
+use serde::Deserialize;
+
 // fin re-exports ---------------------------------------------------------- //
 
-/// Type alias for key, i.e. sub-stream identifier, type.
-pub type Key = usize;
-/// Type alias for timestamp type.
+/// Type alias for timestamp type. Timestamps are counted in the [`TimeUnit`]
+/// declared for the source data, as are all durations derived from them.
 pub type Timestamp = usize;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Resolution of [`Timestamp`]s.
+pub enum TimeUnit {
+  #[default]
+  Seconds,
+  Milliseconds,
+  Microseconds,
+}
+
+impl TimeUnit {
+  /// Returns the number of [`Timestamp`] units in one second.
+  pub fn per_second(&self) -> Timestamp {
+    match self {
+      Self::Seconds => 1,
+      Self::Milliseconds => 1_000,
+      Self::Microseconds => 1_000_000,
+    }
+  }
+}
+
 /// Trait for fluents.
 pub trait FluentTrait {
   fn name(&self) -> &str;
@@ -39,4 +63,22 @@ pub trait FluentTrait {
   fn timestamp(&self) -> Timestamp;
   fn boxed_value(&self) -> Box<dyn ValueType>;
   fn last_change(&self) -> Timestamp;
+  fn changes(&self) -> &[Timestamp];
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::TimeUnit;
+
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn time_unit_test() {
+    assert_eq!(TimeUnit::Seconds.per_second(), 1);
+    assert_eq!(TimeUnit::Milliseconds.per_second(), 1_000);
+    assert_eq!(TimeUnit::Microseconds.per_second(), 1_000_000);
+  }
 }
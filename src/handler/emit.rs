@@ -0,0 +1,122 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use crate::fluent::{Fluent, FluentTrait, Key, Timestamp};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+/// Selects which fluents a [`Handler`](super::Handler) publishes, or which
+/// fluents a sink writes.
+pub enum Emit {
+  /// Every fluent, whether its value has changed or not.
+  #[default]
+  Always,
+  /// Only fluents whose value has changed, plus, if a heartbeat is given, a
+  /// fluent whenever the last one for the same keys is at least the heartbeat
+  /// old, in the timestamp unit of the source.
+  ///
+  /// Note that handlers with a [`KeyDependency::Concurrent`] on a fluent
+  /// emitted on change only see it at the timestamps it changed at.
+  ///
+  /// [`KeyDependency::Concurrent`]: super::KeyDependency::Concurrent
+  OnChange {
+    #[serde(default)]
+    heartbeat: Option<Timestamp>,
+  },
+}
+
+
+#[derive(Debug)]
+/// Passes on fluents according to an [`Emit`] setting, keeping track of when
+/// a fluent was last passed on for each name and keys.
+pub struct EmitFilter {
+  emit:         Emit,
+  last_emitted: HashMap<(String, Vec<Key>), Timestamp>,
+}
+
+impl EmitFilter {
+  pub fn new(emit: Emit) -> Self {
+    Self { emit,
+           last_emitted: HashMap::new() }
+  }
+
+  /// Returns whether the fluent is to be passed on. A fluent's value has
+  /// changed if its `last_change` is its timestamp.
+  pub fn admits(&mut self, fluent: &Fluent) -> bool {
+    let heartbeat = match self.emit {
+      Emit::Always => return true,
+      Emit::OnChange { heartbeat } => heartbeat,
+    };
+
+    let id = (fluent.name().to_owned(), fluent.keys().to_vec());
+    let timestamp = fluent.timestamp();
+    let changed = fluent.last_change() == timestamp;
+    let due = match (self.last_emitted.get(&id), heartbeat) {
+      (None, _) => true,
+      (Some(&last_emitted), Some(heartbeat)) => {
+        timestamp >= last_emitted + heartbeat
+      }
+      (Some(_), None) => false,
+    };
+
+    if changed || due {
+      self.last_emitted.insert(id, timestamp);
+    }
+    changed || due
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Emit, EmitFilter};
+  use crate::fluent::{Fluent, Key};
+
+  use pretty_assertions::assert_eq;
+
+
+  fn admitted(emit: Emit, values: &[bool]) -> Vec<usize> {
+    let mut filter = EmitFilter::new(emit);
+    let mut fluent =
+      Fluent::new("near_coast", &[Key::from(1)], 0, Box::new(false));
+
+    let mut admitted = Vec::new();
+    for (timestamp, value) in values.iter().enumerate() {
+      fluent.update(timestamp, Box::new(*value));
+      if filter.admits(&fluent) {
+        admitted.push(timestamp);
+      }
+    }
+    admitted
+  }
+
+  #[test]
+  fn emit_test() {
+    let values = &[false, false, true, true, true, true, true, false, false];
+
+    assert_eq!(admitted(Emit::Always, values),
+               vec![0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(admitted(Emit::OnChange { heartbeat: None }, values),
+               vec![0, 2, 7]);
+    assert_eq!(admitted(Emit::OnChange { heartbeat: Some(3) }, values),
+               vec![0, 2, 5, 7]);
+  }
+
+  #[test]
+  fn emit_config_test() {
+    let emit: Emit = toml::from_str("mode = \"on_change\"").unwrap();
+    assert_eq!(emit, Emit::OnChange { heartbeat: None });
+
+    let emit: Emit =
+      toml::from_str("mode = \"on_change\"\nheartbeat = 600").unwrap();
+    assert_eq!(emit, Emit::OnChange { heartbeat: Some(600), });
+  }
+}
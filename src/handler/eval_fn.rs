@@ -12,16 +12,18 @@ use std::sync::Arc;
 
 
 /// Helper type for the closure objects stored in the [`EvalFn`] struct.
-type FnType<'a> = Arc<dyn (Fn(Vec<Fluent>,
-                           Database)
-                           -> BoxFuture<'a, Option<Box<dyn ValueType>>>)
-                        + Send
-                        + Sync>;
+pub(super) type FnType<'a> =
+  Arc<dyn (Fn(Vec<Fluent>,
+              Database)
+              -> BoxFuture<'a, Option<Box<dyn ValueType>>>)
+        + Send
+        + Sync>;
 
 
 /// Wrapper struct for closures which are used to evaluate fluents.
 pub struct EvalFn {
-  f: FnType<'static>,
+  f:             FnType<'static>,
+  terminated_at: Option<FnType<'static>>,
 }
 
 impl EvalFn {
@@ -29,10 +31,23 @@ impl EvalFn {
   /// defined code section, i.e. `EvalFn::specify(/* ... /*)` is deemed _more
   /// obvious_ in terms of naming than, say, `EvalFn::new(/* ... */)` would be.
   pub fn specify(f: FnType<'static>) -> Self {
-    Self { f }
+    Self { f,
+           terminated_at: None }
   }
 
-  pub fn into_inner(self) -> FnType<'static> {
-    self.f
+  /// Constructor for event calculus style fluents, which are boolean: per key,
+  /// the fluent starts to hold whenever `initiated_at` evaluates to `true` and
+  /// keeps holding until `terminated_at` does.
+  pub fn initiated_terminated(initiated_at: FnType<'static>,
+                              terminated_at: FnType<'static>)
+                              -> Self {
+    Self { f:             initiated_at,
+           terminated_at: Some(terminated_at), }
+  }
+
+  /// Splits the [`EvalFn`] into the closure computing the value of the
+  /// fluent, or its initiation condition, and its termination condition.
+  pub fn into_parts(self) -> (FnType<'static>, Option<FnType<'static>>) {
+    (self.f, self.terminated_at)
   }
 }
@@ -0,0 +1,162 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use crate::fluent::{Fluent, FluentTrait, Timestamp, ValueType};
+
+use tracing::warn;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// Outcome of evaluating the initiation and termination conditions of an
+/// event calculus style fluent for one key at one point in time.
+pub struct Transition {
+  pub initiated:  bool,
+  pub terminated: bool,
+}
+
+impl Transition {
+  /// Interprets the results of the two conditions, either of which counts as
+  /// not met unless it is `true`.
+  pub fn from_conditions(initiated: Option<Box<dyn ValueType>>,
+                         terminated: Option<Box<dyn ValueType>>)
+                         -> Self {
+    Self { initiated:  is_met(initiated),
+           terminated: is_met(terminated), }
+  }
+
+  /// Returns whether the fluent holds after the transition, given whether it
+  /// held before: it holds from the time it is initiated, by inertia for as
+  /// long as it is not terminated. A fluent both initiated and terminated at
+  /// the same time holds, as a new interval starts where the old one ends.
+  pub fn holds(&self, held: bool) -> bool {
+    self.initiated || (held && !self.terminated)
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Truth interval of a boolean fluent, from the time it started to hold
+/// until it stopped, if it has.
+pub struct Interval {
+  pub start: Timestamp,
+  pub end:   Option<Timestamp>,
+}
+
+impl Interval {
+  /// Returns whether the fluent holds at `at` within this interval, which
+  /// includes its start but not its end.
+  pub fn contains(&self, at: Timestamp) -> bool {
+    self.start <= at && self.end.is_none_or(|end| at < end)
+  }
+
+  /// Returns how long the interval lasted, or has lasted as of `at` if it has
+  /// not ended.
+  pub fn duration(&self, at: Timestamp) -> Timestamp {
+    self.end.unwrap_or(at).saturating_sub(self.start)
+  }
+}
+
+
+/// Returns the truth intervals of a boolean fluent, oldest first, of which
+/// the last has not ended if the fluent holds. Past intervals are known from
+/// the changes the fluent keeps, see [`Fluent::keep_changes`]; a
+/// [`Handler`](super::Handler) keeps those of its boolean dependencies for
+/// its buffer timeout. Other fluents do not hold at all.
+pub fn holds_for(fluent: &Fluent) -> Vec<Interval> {
+  if !matches!(fluent, Fluent::Boolean(_)) {
+    return Vec::new();
+  }
+
+  // the value alternates with every change, the last one to the current value
+  let mut changes = fluent.changes().to_vec();
+  changes.push(fluent.last_change());
+  let last = changes.len() - 1;
+  let holds = fluent.value::<bool>();
+  (0..=last).filter(|i| (last - i).is_multiple_of(2) == holds)
+            .map(|i| Interval { start: changes[i],
+                                end:   changes.get(i + 1).copied(), })
+            .collect()
+}
+
+
+/// Returns whether a boolean fluent holds at `at`, as far as its truth
+/// intervals are known, see [`holds_for`].
+pub fn holds_at(fluent: &Fluent, at: Timestamp) -> bool {
+  holds_for(fluent).iter()
+                   .any(|interval| interval.contains(at))
+}
+
+
+fn is_met(value: Option<Box<dyn ValueType>>) -> bool {
+  match value.map(|value| value.downcast::<bool>()) {
+    Some(Ok(met)) => *met,
+    Some(Err(value)) => {
+      warn!("condition evaluated to {:?} instead of a boolean", value);
+      false
+    }
+    None => false,
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{holds_at, holds_for, Interval, Transition};
+  use crate::fluent::{Fluent, Key};
+
+  use pretty_assertions::assert_eq;
+
+
+  #[test]
+  fn transition_test() {
+    let transition = |initiated: bool, terminated: bool| {
+      Transition::from_conditions(Some(Box::new(initiated)),
+                                  Some(Box::new(terminated)))
+    };
+
+    assert!(transition(true, false).holds(false));
+    assert!(!transition(false, false).holds(false));
+    assert!(transition(false, false).holds(true));
+    assert!(!transition(false, true).holds(true));
+    assert!(transition(true, true).holds(true));
+
+    // conditions without a boolean value count as not met
+    let transition = Transition::from_conditions(Some(Box::new(1.0)), None);
+    assert_eq!(transition, Transition::default());
+  }
+
+  #[test]
+  fn holds_for_test() {
+    let keys = &[Key::from(1)];
+    let interval = |start, end| Interval { start, end };
+
+    let mut fluent = Fluent::new("rendez_vous", keys, 100, Box::new(true));
+    assert_eq!(holds_for(&fluent), vec![interval(100, None)]);
+    fluent.update(160, Box::new(true));
+    assert_eq!(holds_for(&fluent)[0].duration(200), 100);
+
+    // only the current interval is known unless changes are kept
+    fluent.update(220, Box::new(false));
+    assert_eq!(holds_for(&fluent), vec![]);
+
+    fluent.keep_changes(1_000);
+    fluent.update(300, Box::new(true));
+    fluent.update(400, Box::new(false));
+    fluent.update(500, Box::new(true));
+    assert_eq!(holds_for(&fluent),
+               vec![interval(300, Some(400)), interval(500, None)]);
+    assert_eq!(holds_for(&fluent)[0].duration(600), 100);
+    assert!(holds_at(&fluent, 300));
+    assert!(!holds_at(&fluent, 400));
+    assert!(holds_at(&fluent, 600));
+    assert!(!holds_at(&fluent, 250));
+
+    let speed = Fluent::new("speed", keys, 0, Box::new(7.5));
+    assert_eq!(holds_for(&speed), vec![]);
+    assert!(!holds_at(&speed, 0));
+  }
+}
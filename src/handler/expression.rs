@@ -0,0 +1,602 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::{holds_at, holds_for};
+use crate::fluent::{Fluent, FluentTrait, Timestamp, ValueType};
+
+use eyre::{bail, eyre, Result};
+use std::{cmp::Ordering, iter::Peekable, str::CharIndices};
+
+
+#[derive(Clone, Debug, PartialEq)]
+/// Value an [`Expression`] evaluates to.
+pub enum Value {
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Text(String),
+}
+
+impl Value {
+  fn from_fluent(fluent: &Fluent) -> Result<Self> {
+    Ok(match fluent {
+         Fluent::Boolean(_) => Self::Bool(fluent.value::<bool>()),
+         Fluent::Integer(_) => Self::Int(fluent.value::<i32>() as i64),
+         Fluent::LongInt(_) => Self::Int(fluent.value::<i64>()),
+         Fluent::FloatPt(_) => Self::Float(fluent.value::<f64>()),
+         Fluent::Textual(_) => Self::Text(fluent.value::<String>()),
+         _ => bail!("'{}' is of a type expressions cannot use", fluent.name()),
+       })
+  }
+
+  /// Boxes the value to be published as a fluent.
+  pub fn boxed(self) -> Box<dyn ValueType> {
+    match self {
+      Self::Bool(value) => Box::new(value),
+      Self::Int(value) => Box::new(value),
+      Self::Float(value) => Box::new(value),
+      Self::Text(value) => Box::new(value),
+    }
+  }
+
+  fn as_bool(&self) -> Result<bool> {
+    match self {
+      Self::Bool(value) => Ok(*value),
+      value => bail!("expected a boolean, found {:?}", value),
+    }
+  }
+
+  fn as_float(&self) -> Option<f64> {
+    match self {
+      Self::Int(value) => Some(*value as f64),
+      Self::Float(value) => Some(*value),
+      _ => None,
+    }
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+  Not,
+  Neg,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+  Or,
+  And,
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Rem,
+}
+
+impl BinaryOp {
+  /// Operators by precedence level, from loosest to tightest binding.
+  const LEVELS: [&'static [(&'static str, Self)]; 5] =
+    [&[("||", Self::Or)],
+     &[("&&", Self::And)],
+     &[("==", Self::Eq),
+       ("!=", Self::Ne),
+       ("<=", Self::Le),
+       (">=", Self::Ge),
+       ("<", Self::Lt),
+       (">", Self::Gt)],
+     &[("+", Self::Add), ("-", Self::Sub)],
+     &[("*", Self::Mul), ("/", Self::Div), ("%", Self::Rem)]];
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+/// A rule expression, such as `speed > 5.0` or `a && !b`, computing the value
+/// of a fluent from the values of its dependencies.
+///
+/// Expressions are made up of boolean, integer, float and double-quoted string
+/// literals, the names of dependencies, `$0`, `$1`, ... for dependencies by
+/// position (e.g. both fluents of a non-concurrent pair), parentheses, `!`
+/// and `-` as well as, from loosest to tightest binding, `||`, `&&`,
+/// comparisons, `+`/`-` and `*`/`/`/`%`.
+///
+/// For boolean dependencies, `holds_at(a)` is whether `a` holds and
+/// `holds_for(a)` how long it has held as of the latest dependency, i.e. the
+/// duration of its current truth interval, or `0` if it does not hold. Given
+/// a duration `d` as well, `holds_at(a, d)` is whether `a` held `d` before
+/// the latest dependency and `holds_for(a, d)` how long it held within the
+/// last `d`, as far as its past truth intervals are kept.
+pub enum Expression {
+  Literal(Value),
+  Dependency(usize),
+  HoldsAt(usize, Option<Box<Expression>>),
+  HoldsFor(usize, Option<Box<Expression>>),
+  Unary(UnaryOp, Box<Expression>),
+  Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+  /// Parses an expression, resolving names against the given dependencies.
+  pub fn parse(source: &str, dependencies: &[String]) -> Result<Self> {
+    let mut parser = Parser { source,
+                              chars: source.char_indices().peekable(),
+                              dependencies };
+    let expression = parser.binary(0)?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+      None => Ok(expression),
+      Some(&(position, _)) => Err(parser.error(position, "unexpected input")),
+    }
+  }
+
+  /// Evaluates the expression for the given dependency fluents, which are in
+  /// the order of the dependencies.
+  pub fn eval(&self, fluents: &[Fluent]) -> Result<Value> {
+    match self {
+      Self::Literal(value) => Ok(value.clone()),
+      Self::Dependency(position) => {
+        Value::from_fluent(dependency(fluents, *position)?)
+      }
+      Self::HoldsAt(position, before) => {
+        let fluent = boolean(fluents, *position)?;
+        let at = latest(fluents).saturating_sub(duration(before, fluents)?);
+        Ok(Value::Bool(holds_at(fluent, at)))
+      }
+      Self::HoldsFor(position, within) => {
+        let fluent = boolean(fluents, *position)?;
+        let at = latest(fluents);
+        let intervals = holds_for(fluent);
+        let held = match within {
+          None => intervals.last()
+                           .filter(|interval| interval.end.is_none())
+                           .map_or(0, |interval| interval.duration(at)),
+          Some(_) => {
+            let from = at.saturating_sub(duration(within, fluents)?);
+            intervals.iter()
+                     .map(|interval| {
+                       let end = interval.end.unwrap_or(at).min(at);
+                       end.saturating_sub(interval.start.max(from))
+                     })
+                     .sum()
+          }
+        };
+        Ok(Value::Int(held as i64))
+      }
+      Self::Unary(op, operand) => match (op, operand.eval(fluents)?) {
+        (UnaryOp::Not, value) => Ok(Value::Bool(!value.as_bool()?)),
+        (UnaryOp::Neg, Value::Int(value)) => Ok(Value::Int(-value)),
+        (UnaryOp::Neg, Value::Float(value)) => Ok(Value::Float(-value)),
+        (UnaryOp::Neg, value) => bail!("cannot negate {:?}", value),
+      },
+      // evaluate the right hand side only if it makes a difference
+      Self::Binary(BinaryOp::Or, lhs, rhs) => {
+        Ok(Value::Bool(lhs.eval(fluents)?.as_bool()?
+                       || rhs.eval(fluents)?.as_bool()?))
+      }
+      Self::Binary(BinaryOp::And, lhs, rhs) => {
+        Ok(Value::Bool(lhs.eval(fluents)?.as_bool()?
+                       && rhs.eval(fluents)?.as_bool()?))
+      }
+      Self::Binary(op, lhs, rhs) => {
+        binary(*op, lhs.eval(fluents)?, rhs.eval(fluents)?)
+      }
+    }
+  }
+}
+
+
+fn dependency(fluents: &[Fluent], position: usize) -> Result<&Fluent> {
+  fluents.get(position)
+         .ok_or_else(|| eyre!("no dependency at ${}", position))
+}
+
+
+fn boolean(fluents: &[Fluent], position: usize) -> Result<&Fluent> {
+  let fluent = dependency(fluents, position)?;
+  match fluent {
+    Fluent::Boolean(_) => Ok(fluent),
+    _ => bail!("'{}' is not a boolean and cannot hold", fluent.name()),
+  }
+}
+
+
+/// Returns the timestamp of the latest dependency.
+fn latest(fluents: &[Fluent]) -> Timestamp {
+  fluents.iter().map(|f| f.timestamp()).max().unwrap_or(0)
+}
+
+
+/// Evaluates the duration given to a function, if any, which defaults to 0.
+fn duration(duration: &Option<Box<Expression>>,
+            fluents: &[Fluent])
+            -> Result<Timestamp> {
+  match duration.as_ref().map(|d| d.eval(fluents)).transpose()? {
+    None => Ok(0),
+    Some(Value::Int(value)) if value >= 0 => Ok(value as Timestamp),
+    Some(value) => bail!("expected a duration, found {:?}", value),
+  }
+}
+
+
+fn binary(op: BinaryOp, lhs: Value, rhs: Value) -> Result<Value> {
+  use BinaryOp::*;
+
+  Ok(match op {
+       Eq | Ne | Lt | Le | Gt | Ge => {
+         let ordering =
+           compare(&lhs, &rhs).ok_or_else(|| {
+                                eyre!("cannot compare {:?} and {:?}", lhs, rhs)
+                              })?;
+         Value::Bool(match op {
+                       Eq => ordering.is_eq(),
+                       Ne => ordering.is_ne(),
+                       Lt => ordering.is_lt(),
+                       Le => ordering.is_le(),
+                       Gt => ordering.is_gt(),
+                       _ => ordering.is_ge(),
+                     })
+       }
+       Add | Sub | Mul | Div | Rem => match (&lhs, &rhs) {
+         (Value::Int(l), Value::Int(r)) => {
+           let value = match op {
+             Add => l.checked_add(*r),
+             Sub => l.checked_sub(*r),
+             Mul => l.checked_mul(*r),
+             Div => l.checked_div(*r),
+             _ => l.checked_rem(*r),
+           };
+           Value::Int(value.ok_or_else(|| {
+                             eyre!("{:?} {:?} {:?} overflows or divides by \
+                                    zero",
+                                   lhs,
+                                   op,
+                                   rhs)
+                           })?)
+         }
+         (l, r) => match (l.as_float(), r.as_float()) {
+           (Some(l), Some(r)) => Value::Float(match op {
+                                                Add => l + r,
+                                                Sub => l - r,
+                                                Mul => l * r,
+                                                Div => l / r,
+                                                _ => l % r,
+                                              }),
+           _ => bail!("cannot apply {:?} to {:?} and {:?}", op, lhs, rhs),
+         },
+       },
+       Or | And => unreachable!("logical operators are evaluated lazily"),
+     })
+}
+
+
+/// Compares values of the same type, or numbers of either type.
+fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+  match (lhs, rhs) {
+    (Value::Bool(l), Value::Bool(r)) => l.partial_cmp(r),
+    (Value::Text(l), Value::Text(r)) => l.partial_cmp(r),
+    (l, r) => l.as_float()?.partial_cmp(&r.as_float()?),
+  }
+}
+
+
+/// Recursive descent parser for [`Expression`]s.
+struct Parser<'a> {
+  source:       &'a str,
+  chars:        Peekable<CharIndices<'a>>,
+  dependencies: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+  /// Parses operands joined by operators of the given precedence level or
+  /// tighter binding ones.
+  fn binary(&mut self, level: usize) -> Result<Expression> {
+    if level == BinaryOp::LEVELS.len() {
+      return self.unary();
+    }
+
+    let mut lhs = self.binary(level + 1)?;
+    'operands: loop {
+      self.skip_whitespace();
+      for &(symbol, op) in BinaryOp::LEVELS[level] {
+        if self.eat(symbol) {
+          let rhs = self.binary(level + 1)?;
+          lhs = Expression::Binary(op, Box::new(lhs), Box::new(rhs));
+          // comparisons do not chain
+          match level == 2 {
+            true => return Ok(lhs),
+            false => continue 'operands,
+          }
+        }
+      }
+      return Ok(lhs);
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expression> {
+    self.skip_whitespace();
+    let op = match self.chars.peek() {
+      Some((_, '!')) => UnaryOp::Not,
+      Some((_, '-')) => UnaryOp::Neg,
+      _ => return self.primary(),
+    };
+    self.chars.next();
+    Ok(Expression::Unary(op, Box::new(self.unary()?)))
+  }
+
+  fn primary(&mut self) -> Result<Expression> {
+    let (start, c) = match self.chars.next() {
+      Some(next) => next,
+      None => bail!("unexpected end of expression '{}'", self.source),
+    };
+
+    match c {
+      '(' => {
+        let expression = self.binary(0)?;
+        self.skip_whitespace();
+        let position = self.position();
+        match self.eat(")") {
+          true => Ok(expression),
+          false => Err(self.error(position, "expected ')'")),
+        }
+      }
+      '"' => {
+        let text = self.take_while(|c| c != '"');
+        match self.eat("\"") {
+          true => Ok(Expression::Literal(Value::Text(text.to_owned()))),
+          false => Err(self.error(start, "unterminated string")),
+        }
+      }
+      '$' => {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        match digits.parse() {
+          Ok(position) => Ok(Expression::Dependency(position)),
+          Err(_) => Err(self.error(start, "expected a dependency position")),
+        }
+      }
+      c if c.is_ascii_digit() => {
+        let rest = self.take_while(is_number);
+        let literal =
+          self.source[start..start + 1 + rest.len()].replace('_', "");
+        if let Ok(value) = literal.parse() {
+          Ok(Expression::Literal(Value::Int(value)))
+        } else if let Ok(value) = literal.parse() {
+          Ok(Expression::Literal(Value::Float(value)))
+        } else {
+          Err(self.error(start, "invalid number"))
+        }
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let source = self.source;
+        let rest = self.take_while(is_name);
+        let name = &source[start..start + c.len_utf8() + rest.len()];
+        match name {
+          "true" => Ok(Expression::Literal(Value::Bool(true))),
+          "false" => Ok(Expression::Literal(Value::Bool(false))),
+          "holds_at" | "holds_for" if self.continues_with("(") => {
+            let (position, duration) = self.arguments()?;
+            Ok(match name {
+                 "holds_at" => Expression::HoldsAt(position, duration),
+                 _ => Expression::HoldsFor(position, duration),
+               })
+          }
+          name => match self.dependencies.iter().position(|d| d == name) {
+            Some(position) => Ok(Expression::Dependency(position)),
+            None => Err(self.error(start, "not a dependency")),
+          },
+        }
+      }
+      _ => Err(self.error(start, "unexpected character")),
+    }
+  }
+
+  /// Parses the parenthesized dependency a function is applied to, and the
+  /// duration optionally given after it.
+  fn arguments(&mut self) -> Result<(usize, Option<Box<Expression>>)> {
+    self.skip_whitespace();
+    self.eat("(");
+    self.skip_whitespace();
+    let start = self.position();
+    let position = match self.primary()? {
+      Expression::Dependency(position) => position,
+      _ => return Err(self.error(start, "expected a dependency")),
+    };
+    self.skip_whitespace();
+    let duration = match self.eat(",") {
+      true => Some(Box::new(self.binary(0)?)),
+      false => None,
+    };
+    self.skip_whitespace();
+    let end = self.position();
+    match self.eat(")") {
+      true => Ok((position, duration)),
+      false => Err(self.error(end, "expected ')'")),
+    }
+  }
+
+  /// Returns whether the input continues with the given symbol, after any
+  /// whitespace.
+  fn continues_with(&mut self, symbol: &str) -> bool {
+    let position = self.position();
+    self.source[position..].trim_start().starts_with(symbol)
+  }
+
+  /// Consumes the given symbol if the input continues with it.
+  fn eat(&mut self, symbol: &str) -> bool {
+    let position = self.position();
+    if !self.source[position..].starts_with(symbol) {
+      return false;
+    }
+    for _ in symbol.chars() {
+      self.chars.next();
+    }
+    true
+  }
+
+  fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+    let start = self.position();
+    while matches!(self.chars.peek(), Some(&(_, c)) if predicate(c)) {
+      self.chars.next();
+    }
+    &self.source[start..self.position()]
+  }
+
+  fn skip_whitespace(&mut self) {
+    self.take_while(char::is_whitespace);
+  }
+
+  fn position(&mut self) -> usize {
+    self.chars
+        .peek()
+        .map_or(self.source.len(), |&(position, _)| position)
+  }
+
+  fn error(&self, position: usize, message: &str) -> eyre::Report {
+    eyre!("{} at position {} of expression '{}'",
+          message,
+          position,
+          self.source)
+  }
+}
+
+
+fn is_number(c: char) -> bool {
+  c.is_ascii_digit() || c == '.' || c == '_'
+}
+
+fn is_name(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Expression, Value};
+  use crate::{fluent::{Fluent, Key},
+              stringvec};
+
+  use pretty_assertions::assert_eq;
+
+
+  fn eval(source: &str, fluents: &[Fluent]) -> Value {
+    let dependencies = stringvec!["speed", "near_coast", "ship_type"];
+    Expression::parse(source, &dependencies).unwrap()
+                                            .eval(fluents)
+                                            .unwrap()
+  }
+
+  fn fluents() -> Vec<Fluent> {
+    let keys = &[Key::from(1)];
+    vec![Fluent::new("speed", keys, 0, Box::new(7.5)),
+         Fluent::new("near_coast", keys, 0, Box::new(false)),
+         Fluent::new("ship_type", keys, 0, Box::new(31))]
+  }
+
+  #[test]
+  fn eval_test() {
+    let fluents = fluents();
+
+    assert_eq!(eval("speed > 5.0", &fluents), Value::Bool(true));
+    assert_eq!(eval("speed > 5.0 && !near_coast", &fluents),
+               Value::Bool(true));
+    assert_eq!(eval("near_coast || speed <= 5", &fluents),
+               Value::Bool(false));
+    assert_eq!(eval("$2 == 31 || $2 == 32", &fluents), Value::Bool(true));
+    assert_eq!(eval("(speed - 1.5) * 2", &fluents), Value::Float(12.0));
+    assert_eq!(eval("-ship_type + 2 * 3 % 4", &fluents), Value::Int(-29));
+    assert_eq!(eval("\"tug\" != \"pilot\"", &fluents), Value::Bool(true));
+    assert_eq!(eval("1_000", &fluents), Value::Int(1_000));
+  }
+
+  #[test]
+  fn holds_test() {
+    let keys = &[Key::from(1)];
+    let mut near_coast = Fluent::new("near_coast", keys, 0, Box::new(false));
+    near_coast.keep_changes(1_000);
+    near_coast.update(20, Box::new(true));
+    near_coast.update(60, Box::new(false));
+    near_coast.update(100, Box::new(true));
+    near_coast.update(150, Box::new(true));
+    let fluents = vec![Fluent::new("speed", keys, 400, Box::new(7.5)),
+                       near_coast,
+                       Fluent::new("ship_type", keys, 0, Box::new(31))];
+
+    assert_eq!(eval("holds_at(near_coast)", &fluents), Value::Bool(true));
+    // measured up to the latest dependency
+    assert_eq!(eval("holds_for(near_coast)", &fluents), Value::Int(300));
+    assert_eq!(eval("holds_for ( $1 ) > 200 && speed > 5.0", &fluents),
+               Value::Bool(true));
+    assert_eq!(eval("holds_for(near_coast)", &self::fluents()),
+               Value::Int(0));
+
+    // looking back from the latest dependency at 400
+    assert_eq!(eval("holds_at(near_coast, 360)", &fluents),
+               Value::Bool(true));
+    assert_eq!(eval("holds_at(near_coast, 2 * 170)", &fluents),
+               Value::Bool(false));
+    assert_eq!(eval("holds_for(near_coast, 400)", &fluents),
+               Value::Int(340));
+    assert_eq!(eval("holds_for(near_coast, 100)", &fluents),
+               Value::Int(100));
+    let dependencies = stringvec!["near_coast"];
+    let before = Expression::parse("holds_at(near_coast, -1)", &dependencies);
+    assert!(before.unwrap().eval(&fluents[1..]).is_err());
+
+    let dependencies = stringvec!["speed", "holds_for"];
+    let holds_for = Expression::parse("holds_for", &dependencies).unwrap();
+    assert_eq!(holds_for, Expression::Dependency(1));
+    assert!(Expression::parse("holds_for(1)", &dependencies).is_err());
+    assert!(Expression::parse("holds_for(speed", &dependencies).is_err());
+    assert!(Expression::parse("holds_for(speed)", &dependencies).unwrap()
+                                                                .eval(&fluents)
+                                                                .is_err());
+  }
+
+  #[test]
+  fn eval_error_test() {
+    let fluents = fluents();
+    let dependencies = stringvec!["speed", "near_coast", "ship_type"];
+    let eval = |source| {
+      Expression::parse(source, &dependencies).unwrap()
+                                              .eval(&fluents)
+    };
+
+    assert!(eval("speed && near_coast").is_err());
+    assert!(eval("ship_type / 0").is_err());
+    assert!(eval("!speed").is_err());
+    assert!(eval("$3").is_err());
+    assert!(eval("near_coast == 0").is_err());
+    // the right hand side is not evaluated and therefore not a type error
+    assert!(eval("near_coast && speed").is_ok());
+  }
+
+  #[test]
+  fn parse_error_test() {
+    let dependencies = stringvec!["speed"];
+    let error = |source| {
+      Expression::parse(source, &dependencies).unwrap_err()
+                                              .to_string()
+    };
+
+    assert_eq!(error("sped > 5.0"),
+               "not a dependency at position 0 of expression 'sped > 5.0'");
+    assert_eq!(error("speed > 5.0)"),
+               "unexpected input at position 11 of expression 'speed > 5.0)'");
+    assert_eq!(error("(speed > 5.0"),
+               "expected ')' at position 12 of expression '(speed > 5.0'");
+    assert_eq!(error("1 < speed < 5"),
+               "unexpected input at position 10 of expression '1 < speed < \
+                5'");
+    assert!(Expression::parse("speed >", &dependencies).is_err());
+    assert!(Expression::parse("\"tug", &dependencies).is_err());
+  }
+}
@@ -4,24 +4,114 @@
 // received a copy of this license along with the source code. If that is not
 // the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
 
-use super::EvalFn;
+use super::{event_calculus::Transition,
+            pairing::{Pairing, PairingIndex},
+            watermark::{side_channel, Admission, Watermark},
+            Emit,
+            EmitFilter,
+            EvalFn,
+            LatePolicy,
+            Lateness};
 use crate::{app_core::{Database, Node, NodeRx, NodeTx},
-            fluent::{Fluent, FluentTrait, Key}};
+            fluent::{Fluent,
+                     FluentTrait,
+                     Key,
+                     TimeUnit,
+                     Timestamp,
+                     ValueType}};
 
 use async_trait::async_trait;
 use derivative::Derivative;
 use eyre::{bail, Result};
+use serde::Deserialize;
 use std::{collections::BTreeMap,
-          sync::{Arc, Mutex}};
-use tokio_stream::StreamExt;
-use tracing::debug;
+          fmt,
+          sync::{atomic::{AtomicUsize, Ordering},
+                 Arc,
+                 Mutex}};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, warn};
 
 
-#[derive(Debug, PartialEq, Eq)]
+/// Number of fluents a [`Publisher`] queues before the node publishing them
+/// has to wait for the broker, and of evaluations a [`Handler`] starts before
+/// it has to wait for them to finish.
+const QUEUE_CAPACITY: usize = 1_024;
+
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum KeyDependency {
   Static,
+  #[default]
   Concurrent,
-  NonConcurrent { timeout: usize },
+  NonConcurrent {
+    timeout: usize,
+    #[serde(default)]
+    pairing: Option<Pairing>,
+  },
+}
+
+impl KeyDependency {
+  /// Converts the timeout, which is given in seconds, to `time_unit`, the
+  /// unit of the timestamps the [`Handler`] sees.
+  pub fn in_unit(self, time_unit: TimeUnit) -> Self {
+    match self {
+      Self::NonConcurrent { timeout, pairing } => {
+        Self::NonConcurrent { timeout: timeout * time_unit.per_second(),
+                              pairing }
+      }
+      key_dependency => key_dependency,
+    }
+  }
+}
+
+impl fmt::Display for KeyDependency {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Static => write!(f, "static"),
+      Self::Concurrent => write!(f, "concurrent"),
+      Self::NonConcurrent { timeout, pairing } => {
+        write!(f, "non-concurrent (timeout {})", timeout)?;
+        match pairing {
+          Some(pairing) => write!(f, " {}", pairing),
+          None => Ok(()),
+        }
+      }
+    }
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+/// Decides when the dependencies of a [`Handler`] with a static or concurrent
+/// [`KeyDependency`] line up in time, so the [`Handler`] is evaluated. Has no
+/// effect on non-concurrent ones.
+pub enum Alignment {
+  /// All dependencies have the same timestamp. Each set of them is evaluated
+  /// once.
+  #[default]
+  Exact,
+  /// The timestamps of all dependencies lie within `tolerance` (in the
+  /// timestamp unit of the source) of each other. Evaluated on each update
+  /// for as long as they do.
+  Tolerance { tolerance: usize },
+  /// The latest known value of each dependency is used, whatever its
+  /// timestamp, as long as it has not been pruned from the buffer. Evaluated
+  /// on each update once all dependencies are known, which combines
+  /// dependencies updated at different rates.
+  SampleAndHold,
+}
+
+impl fmt::Display for Alignment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Exact => write!(f, "exact"),
+      Self::Tolerance { tolerance } => write!(f, "within {}", tolerance),
+      Self::SampleAndHold => write!(f, "sample and hold"),
+    }
+  }
 }
 
 
@@ -32,7 +122,9 @@ pub struct HandlerDefinition<'a> {
   pub fluent_name:    &'a str,
   pub dependencies:   &'a [&'a str],
   pub key_dependency: KeyDependency,
+  pub alignment:      Alignment,
   pub database_query: Option<&'a str>,
+  pub emit:           Emit,
   #[derivative(Debug = "ignore")]
   pub eval_fn:        EvalFn,
 }
@@ -47,11 +139,16 @@ pub struct Handler {
   fluent_name:    String,
   dependencies:   Vec<String>,
   key_dependency: KeyDependency,
+  alignment:      Alignment,
+  emit:           Emit,
   #[derivative(Debug = "ignore")]
   eval_fn:        EvalFn,
   database:       Database,
   deps_buffer:    BTreeMap<Vec<Key>, Vec<Fluent>>,
+  pairing_index:  Option<PairingIndex>,
   buffer_timeout: usize,
+  lateness:       Lateness,
+  late_fluents:   Arc<AtomicUsize>,
   node_ch:        Option<(NodeTx, NodeRx)>,
 }
 
@@ -61,6 +158,7 @@ impl Handler {
   /// [`EvalFn`] (which is a wrapper struct for a closure).
   pub async fn new(def: HandlerDefinition<'_>,
                    buffer_timeout: usize,
+                   lateness: Lateness,
                    database: Database)
                    -> Result<Handler> {
     let fluent_name = def.fluent_name.to_owned();
@@ -69,21 +167,68 @@ impl Handler {
                           .map(|e| e.to_string())
                           .collect::<Vec<_>>();
     let key_dependency = def.key_dependency;
+    let alignment = def.alignment;
+    let emit = def.emit;
     let eval_fn = def.eval_fn;
     let database = database.with_template_option(def.database_query);
     let deps_buffer = BTreeMap::new();
+    let pairing_index = match &key_dependency {
+      KeyDependency::NonConcurrent { pairing: Some(pairing),
+                                     .. } => {
+        // NaN and infinite radii would break the grid of the index
+        if !pairing.radius.is_finite() || pairing.radius <= 0.0 {
+          bail!("pairing radius of '{}' must be positive and finite",
+                fluent_name);
+        }
+        if !dependencies.contains(&pairing.dependency) {
+          bail!("pairing dependency '{}' of '{}' is not a dependency",
+                pairing.dependency,
+                fluent_name);
+        }
+        Some(PairingIndex::new(pairing.clone()))
+      }
+      _ => None,
+    };
+    let late_fluents = Arc::new(AtomicUsize::new(0));
     let node_ch = None;
 
     Ok(Self { fluent_name,
               dependencies,
               key_dependency,
+              alignment,
+              emit,
               eval_fn,
               database,
               deps_buffer,
+              pairing_index,
               buffer_timeout,
+              lateness,
+              late_fluents,
               node_ch })
   }
 
+  /// Returns how the keys of the dependencies relate to each other.
+  pub fn key_dependency(&self) -> &KeyDependency {
+    &self.key_dependency
+  }
+
+  /// Returns when the dependencies are considered to line up in time.
+  pub fn alignment(&self) -> Alignment {
+    self.alignment
+  }
+
+  /// Returns whether the evaluation function is given the result of a
+  /// database query.
+  pub fn has_database_query(&self) -> bool {
+    self.database.has_template()
+  }
+
+  /// Returns a handle to the count of fluents which arrived behind the
+  /// watermark of this [`Handler`], to be read once the run is over.
+  pub fn late_fluents(&self) -> Arc<AtomicUsize> {
+    self.late_fluents.clone()
+  }
+
   /// This function contains a lot of the logic which defines the way fluents
   /// evolve over time, i.e. it contains the way dependencies are buffered,
   /// stored and detected to be complete for function evaluation, how
@@ -98,113 +243,306 @@ impl Handler {
     };
 
     let fluent_name = self.fluent_name;
-    let eval_fn = self.eval_fn.into_inner();
+    let (publisher, forwarding) =
+      Publisher::new(&fluent_name, node_tx.clone());
+    let (eval_fn, terminated_at) = self.eval_fn.into_parts();
     let database = self.database;
     let history = Arc::new(Mutex::new(Vec::<Fluent>::new()));
+    let emit_filter = Arc::new(Mutex::new(EmitFilter::new(self.emit)));
+    let mut watermark = Watermark::new(self.lateness.allowed);
+
+    // evaluations run concurrently, but their results are applied one after
+    // the other, in the order the evaluations were started
+    let (evaluations, pending) = mpsc::channel(QUEUE_CAPACITY);
+    let applying = tokio::spawn(apply(fluent_name.clone(),
+                                      pending,
+                                      history.clone(),
+                                      emit_filter.clone(),
+                                      publisher.clone()));
+
+    // applying and publishing stop along with the handler if it is aborted,
+    // e.g. when its rule is reloaded, so nothing is published after that
+    let mut applying = AbortOnDrop(applying);
+    let mut forwarding = AbortOnDrop(forwarding);
+
+    // runs until all subscriptions have ended, then waits for the evaluations
+    // still pending to be published
+    let mut done = false;
+    while !done {
+      // hold fluents back until the watermark has passed them, so they are
+      // evaluated in order, and deal with those arriving behind it. once all
+      // subscriptions have ended, evaluate whatever is still held back.
+      let admission = match node_rx.recv().await? {
+        Some((name, fluent)) => watermark.admit(name, fluent),
+        None => {
+          done = true;
+          Admission::Released(watermark.drain())
+        }
+      };
+      let released = match admission {
+        Admission::Released(released) => released,
+        Admission::Late(name, fluent) => {
+          self.late_fluents.fetch_add(1, Ordering::Relaxed);
+          debug!("'{}' received late fluent '{}' at {}",
+                 fluent_name,
+                 name,
+                 fluent.timestamp());
+          match self.lateness.policy {
+            LatePolicy::Drop => continue,
+            LatePolicy::Correct => vec![(name, fluent)],
+            LatePolicy::SideChannel => {
+              let late_fluent = Fluent::new(&side_channel(&fluent_name),
+                                            fluent.keys(),
+                                            fluent.timestamp(),
+                                            fluent.boxed_value());
+              publisher.publish(late_fluent).await;
+              continue;
+            }
+          }
+        }
+      };
+
+      for (name, mut fluent) in released {
+        let keys = fluent.keys().to_vec();
+        let timestamp = fluent.timestamp();
+
+        // if we have a static key dependency - in other words, if this value
+        // never changes for one key and thus needs to be calculated only
+        // once - look it up in the history and if we have it, return it from
+        // there with an updated timestamp and skip the remainder of the loop
+        if self.key_dependency == KeyDependency::Static {
+          // unwrap here is safe: locking `Mutex` cannot fail
+          let history_fluent = {
+            let mut history = history.lock().unwrap();
+            history.iter_mut()
+                   .find(|f| f.keys() == keys)
+                   .map(|history_fluent| {
+                     history_fluent.update(timestamp,
+                                           history_fluent.boxed_value());
+                     history_fluent.clone()
+                   })
+          };
+          if let Some(history_fluent) = history_fluent {
+            debug!("updating and sending '{}' from history", fluent_name);
+            if !emit_filter.lock().unwrap().admits(&history_fluent) {
+              continue;
+            }
+            publisher.publish(history_fluent).await;
+            continue;
+          }
+        }
 
-    while let Some((name, Ok(fluent))) = node_rx.next().await {
-      let keys = fluent.keys().to_vec();
-      let timestamp = fluent.timestamp();
-      let history_mtx = history.clone();
-
-      // if we have a static key dependency - in other words, if this value
-      // never changes for one key and thus needs to be calculated only once -
-      // look it up in the history and if we have it, return it from there with
-      // an updated timestamp and skip the remainder of the loop
-      if self.key_dependency == KeyDependency::Static {
-        // unwrap here is safe: locking `Mutex` cannot fail
-        let mut history = history_mtx.lock().unwrap();
-        if let Some(history_fluent) =
-          history.iter_mut().find(|f| f.keys() == keys)
-        {
-          debug!("updating and sending '{}' from history", fluent_name);
-          history_fluent.update(timestamp, history_fluent.boxed_value());
-          if let Err(err) = node_tx.send(history_fluent.clone()) {
-            eprintln!("error sending to broker: {}", err);
+        // boolean dependencies keep their past truth intervals, as told by
+        // `holds_for`, for as long as fluents are buffered
+        if matches!(fluent, Fluent::Boolean(_)) {
+          fluent.keep_changes(self.buffer_timeout);
+        }
+
+        // check if the dependency buffer has this key (combination) already
+        match self.deps_buffer.get_mut(&keys) {
+          // if yes...
+          Some(buffer) => {
+            // ... check if we have this fluent (by name) already and...
+            if let Some(buffered_fluent) =
+              buffer.iter_mut().find(|f| f.name() == name)
+            {
+              // ... if yes, update it.
+              buffered_fluent.update(timestamp, fluent.boxed_value())
+            } else {
+              // ... if not, push it into the buffer.
+              buffer.push(fluent)
+            }
+          }
+          // if not, add new entry for this key (combination) to the buffer
+          // with the fluent inside
+          None => {
+            self.deps_buffer.insert(keys.clone(), vec![fluent]);
           }
-          continue;
+        }
+
+        // before we do anything, we prune the buffer of old fluents
+        util::prune_buffer(&mut self.deps_buffer,
+                           timestamp,
+                           self.buffer_timeout);
+
+        // assemble dependency sets to run through
+        let dependency_sets = util::dependency_sets(&mut self.deps_buffer,
+                                                    &keys,
+                                                    timestamp,
+                                                    &self.dependencies,
+                                                    &self.key_dependency,
+                                                    self.alignment,
+                                                    self.pairing_index
+                                                        .as_mut());
+        debug!("{:24} dependency sets: {:?}", fluent_name, dependency_sets);
+        // continue;
+
+        for (dep_keys, dependencies) in dependency_sets.into_iter() {
+          let database = database.clone();
+          let eval_fn = eval_fn.clone();
+          let terminated_at = terminated_at.clone();
+
+          // we've got all the dependencies now - feed them into the eval_fn,
+          // or into both conditions of an event calculus fluent
+          let evaluation = tokio::spawn(async move {
+            match &terminated_at {
+              None => {
+                eval_fn(dependencies, database).await.map(Evaluation::Value)
+              }
+              Some(terminated_at) => {
+                let initiated =
+                  eval_fn(dependencies.clone(), database.clone()).await;
+                let terminated = terminated_at(dependencies, database).await;
+                let transition =
+                  Transition::from_conditions(initiated, terminated);
+                Some(Evaluation::Transition(transition))
+              }
+            }
+          });
+          // waits while too many evaluations are pending, which throttles
+          // the loop; fails only if applying them has stopped
+          let _ = evaluations.send((dep_keys, timestamp, evaluation)).await;
         }
       }
+    }
 
-      // check if the dependency buffer has this key (combination) already
-      match self.deps_buffer.get_mut(&keys) {
-        // if yes...
-        Some(buffer) => {
-          // ... check if we have this fluent (by name) already and...
-          if let Some(buffered_fluent) =
-            buffer.iter_mut().find(|f| f.name() == name)
-          {
-            // ... if yes, update it.
-            buffered_fluent.update(timestamp, fluent.boxed_value())
-          } else {
-            // ... if not, push it into the buffer.
-            buffer.push(fluent)
-          }
+    drop(evaluations);
+    (&mut applying.0).await?;
+    drop(publisher);
+    (&mut forwarding.0).await?;
+    Ok(())
+  }
+}
+
+
+/// Aborts the task of the handle once dropped, so the tasks a [`Handler`]
+/// spawns stop along with it.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+  fn drop(&mut self) {
+    self.0.abort();
+  }
+}
+
+
+/// Result of evaluating the dependencies of a fluent.
+enum Evaluation {
+  Value(Box<dyn ValueType>),
+  Transition(Transition),
+}
+
+
+/// Evaluation started by a [`Handler`], with the keys and timestamp of the
+/// fluent it evaluates.
+type Pending = (Vec<Key>, Timestamp, JoinHandle<Option<Evaluation>>);
+
+
+/// Applies the evaluations of a [`Handler`] in the order they were started,
+/// i.e. in timestamp order, updating the history of its fluent, and
+/// publishes the fluents passing the [`EmitFilter`]. An event calculus fluent
+/// holds by inertia, so whether it holds now depends on whether it held
+/// before, for the same keys: evaluations may finish in any order, but their
+/// transitions have to be applied in order.
+async fn apply(fluent_name: String,
+               mut evaluations: mpsc::Receiver<Pending>,
+               history: Arc<Mutex<Vec<Fluent>>>,
+               emit_filter: Arc<Mutex<EmitFilter>>,
+               publisher: Publisher) {
+  while let Some((keys, timestamp, evaluation)) = evaluations.recv().await {
+    let evaluation = match evaluation.await {
+      Ok(Some(evaluation)) => evaluation,
+      Ok(None) => continue,
+      Err(err) => {
+        warn!("evaluation of '{}' has failed: {}", fluent_name, err);
+        continue;
+      }
+    };
+
+    let fluent = {
+      // unwrap here is safe: locking `Mutex` cannot fail
+      let mut history = history.lock().unwrap();
+      let history_fluent = history.iter_mut().find(|f| f.keys() == keys);
+      let value = match evaluation {
+        Evaluation::Value(value) => value,
+        Evaluation::Transition(transition) => {
+          let held =
+            history_fluent.as_ref().is_some_and(|f| f.value::<bool>());
+          Box::new(transition.holds(held))
+        }
+      };
+      match history_fluent {
+        Some(fluent) => {
+          fluent.update(timestamp, value);
+          fluent.clone()
         }
-        // if not, add new entry for this key (combination) to the buffer with
-        // the fluent inside
         None => {
-          self.deps_buffer.insert(keys.clone(), vec![fluent]);
+          let fluent = Fluent::new(&fluent_name, &keys, timestamp, value);
+          history.push(fluent.clone());
+          fluent
         }
       }
+    };
 
-      // before we do anything, we prune the buffer of old fluents
-      util::prune_buffer(&mut self.deps_buffer,
-                         timestamp,
-                         self.buffer_timeout);
-
-      // assemble dependency sets to run through
-      let dependency_sets = util::dependency_sets(&mut self.deps_buffer,
-                                                  &keys,
-                                                  timestamp,
-                                                  &self.dependencies,
-                                                  &self.key_dependency);
-      debug!("{:24} dependency sets: {:?}", fluent_name, dependency_sets);
-      // continue;
-
-      for (dep_keys, dependencies) in dependency_sets.into_iter() {
-        let fluent_name = fluent_name.clone();
-        let node_tx = node_tx.clone();
-        let database = database.clone();
-        let eval_fn = eval_fn.clone();
-        let history_mtx = history_mtx.clone();
-
-        tokio::spawn(async move {
-          // we've got all the dependencies now - feed them into the eval_fn
-          let value = match eval_fn(dependencies.clone(), database).await {
-            Some(value) => value,
-            None => return,
-          };
+    // unwrap here is safe: locking `Mutex` cannot fail
+    if !emit_filter.lock().unwrap().admits(&fluent) {
+      continue;
+    }
+    publisher.publish(fluent).await;
+  }
+}
 
-          let mut history = history_mtx.lock().unwrap();
-          let fluent = match history.iter_mut().find(|f| f.keys() == dep_keys)
-          {
-            Some(fluent) => {
-              fluent.update(timestamp, value);
-              fluent.clone()
-            }
-            None => {
-              let fluent =
-                Fluent::new(&fluent_name, &dep_keys, timestamp, value);
-              history.push(fluent.clone());
-              fluent
-            }
-          };
 
-          if let Err(err) = node_tx.send(fluent) {
-            eprintln!("unable to send fluent to broker: {}", err);
-          }
-        });
+#[derive(Clone, Debug)]
+/// Publishes the fluents of a [`Handler`], or of a [`Window`](super::Window),
+/// in the order they are queued: a single task forwards them from a bounded
+/// queue to the broker. The receiving loop of the node only waits for the
+/// broker once the queue is full, which throttles it. The broker keeps reading
+/// what a node publishes while waiting for it to catch up on a subscription,
+/// so the wait ends once the broker has made room.
+pub(super) struct Publisher {
+  tx: mpsc::Sender<Fluent>,
+}
+
+impl Publisher {
+  /// Starts the task forwarding to the broker through `node_tx`, which runs
+  /// until all clones of the [`Publisher`] have been dropped, and returns its
+  /// handle along with the [`Publisher`].
+  pub fn new(node_name: &str, node_tx: NodeTx) -> (Self, JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::channel(QUEUE_CAPACITY);
+    let node_name = node_name.to_owned();
+    let forwarding = tokio::spawn(async move {
+      while let Some(fluent) = rx.recv().await {
+        if let Err(err) = node_tx.send(fluent).await {
+          warn!("'{}' unable to send fluent to broker: {}", node_name, err);
+          break;
+        }
       }
-    }
-    Ok(())
+    });
+    (Self { tx }, forwarding)
+  }
+
+  /// Queues a fluent to be sent to the broker, waiting while the queue is
+  /// full.
+  pub async fn publish(&self, fluent: Fluent) {
+    // fails only once the forwarding task has stopped, which it has logged
+    let _ = self.tx.send(fluent).await;
   }
 }
 
 #[async_trait]
 impl Node for Handler {
+  fn name(&self) -> String {
+    self.fluent_name.clone()
+  }
+
   fn publishes(&self) -> Vec<String> {
-    vec![self.fluent_name.clone()]
+    match self.lateness.policy {
+      LatePolicy::SideChannel => {
+        vec![self.fluent_name.clone(), side_channel(&self.fluent_name)]
+      }
+      _ => vec![self.fluent_name.clone()],
+    }
   }
 
   fn subscribes_to(&self) -> Vec<String> {
@@ -217,7 +555,7 @@ impl Node for Handler {
 }
 
 mod util {
-  use super::KeyDependency;
+  use super::{Alignment, KeyDependency, PairingIndex};
   use crate::fluent::{Fluent, FluentTrait, Key, Timestamp};
 
   use array_tool::vec::{Union, Uniq};
@@ -241,6 +579,15 @@ mod util {
            .all(|w| w[0].timestamp() == w[1].timestamp())
   }
 
+  /// Checks if the timestamps of all [`Fluent`]s in collection lie within
+  /// `tolerance` of each other.
+  fn close_timestamps(fluents: &[Fluent], tolerance: usize) -> bool {
+    match fluents.iter().map(|f| f.timestamp()).minmax().into_option() {
+      Some((min, max)) => max - min <= tolerance,
+      None => true,
+    }
+  }
+
   /// Get names of all [`Fluent`]s in collection.
   fn fluent_names(fluents: &[Fluent]) -> Vec<String> {
     fluents.iter().map(|f| f.name().to_string()).collect()
@@ -276,25 +623,47 @@ mod util {
                          keys: &Vec<Key>,
                          timestamp: Timestamp,
                          dependencies: &[String],
-                         key_dependency: &KeyDependency)
+                         key_dependency: &KeyDependency,
+                         alignment: Alignment,
+                         pairing_index: Option<&mut PairingIndex>)
                          -> BTreeMap<Vec<Key>, Vec<Fluent>> {
     let mut dependency_sets = BTreeMap::new();
     let mut key_dependencies = buffer[keys].clone();
     match key_dependency {
       KeyDependency::Static | KeyDependency::Concurrent => {
+        let aligned = match alignment {
+          Alignment::Exact => same_timestamps(&key_dependencies),
+          Alignment::Tolerance { tolerance } => {
+            close_timestamps(&key_dependencies, tolerance)
+          }
+          Alignment::SampleAndHold => true,
+        };
         if equal(&fluent_names(&key_dependencies).unique(), dependencies)
-           && same_timestamps(&key_dependencies)
+           && aligned
         {
           sort_by_given_order(&mut key_dependencies, dependencies);
           dependency_sets.insert(fluent_keys(&key_dependencies),
                                  key_dependencies);
-          buffer.remove(keys);
+          // unless aligned exactly, dependencies are held on to, so the
+          // next update of any of them is combined with the others
+          if alignment == Alignment::Exact {
+            buffer.remove(keys);
+          }
         }
       }
-      KeyDependency::NonConcurrent { timeout } => {
+      KeyDependency::NonConcurrent { timeout, .. } => {
         prune_buffer(buffer, timestamp, *timeout);
-        for (rhs_keys, fluents) in buffer.iter() {
-          if rhs_keys != keys {
+        // with a pairing, only keys within its radius are candidates
+        let candidates = match pairing_index {
+          Some(index) => {
+            index.update(keys, &key_dependencies);
+            index.prune(timestamp, *timeout);
+            index.candidates(keys)
+          }
+          None => buffer.keys().cloned().collect(),
+        };
+        for rhs_keys in candidates.iter().filter(|rhs| *rhs != keys) {
+          if let Some(fluents) = buffer.get(rhs_keys) {
             dependency_sets.insert(merge(keys, rhs_keys),
                                    merge(&key_dependencies, fluents));
           }
@@ -319,3 +688,239 @@ mod util {
 }
 
 // fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{util,
+              Alignment,
+              Handler,
+              HandlerDefinition,
+              KeyDependency,
+              Pairing,
+              PairingIndex,
+              Publisher,
+              QUEUE_CAPACITY};
+  use crate::{app_core::{testing, Database, Node, NodeRx},
+              fluent::{Fluent,
+                       FluentTrait,
+                       Key,
+                       TimeUnit,
+                       Timestamp,
+                       ValueType},
+              handler::{Emit, EvalFn, Lateness},
+              stringvec};
+
+  use futures::future::{self, FutureExt};
+  use pretty_assertions::assert_eq;
+  use std::{collections::BTreeMap, sync::Arc, time::Duration};
+  use tokio::{sync::{broadcast, mpsc},
+              time};
+
+
+  fn buffer(speed_at: Timestamp,
+            heading_at: Timestamp)
+            -> BTreeMap<Vec<Key>, Vec<Fluent>> {
+    let keys = vec![Key::from(1)];
+    let fluents =
+      vec![Fluent::new("speed", &keys, speed_at, Box::new(7.5)),
+           Fluent::new("heading", &keys, heading_at, Box::new(90))];
+    BTreeMap::from([(keys, fluents)])
+  }
+
+  fn timestamps(buffer: &mut BTreeMap<Vec<Key>, Vec<Fluent>>,
+                alignment: Alignment)
+                -> Option<Vec<Timestamp>> {
+    let keys = vec![Key::from(1)];
+    let dependencies = stringvec!["heading", "speed"];
+    let sets = util::dependency_sets(buffer,
+                                     &keys,
+                                     0,
+                                     &dependencies,
+                                     &KeyDependency::Concurrent,
+                                     alignment,
+                                     None);
+    sets.get(&keys)
+        .map(|fluents| fluents.iter().map(|f| f.timestamp()).collect())
+  }
+
+
+  #[tokio::test]
+  async fn publisher_test() {
+    let (tx, mut rx) = mpsc::channel(1);
+    let (publisher, _) = Publisher::new("speed", tx.into());
+    let speed = |timestamp| {
+      Fluent::new("speed", &[Key::from(1)], timestamp, Box::new(7.5))
+    };
+
+    // the queue fills up while the broker does not make room
+    for timestamp in 0..QUEUE_CAPACITY + 2 {
+      publisher.publish(speed(timestamp)).await;
+    }
+    let publish = publisher.publish(speed(QUEUE_CAPACITY + 2));
+    assert!(time::timeout(Duration::from_millis(10), publish).await
+                                                             .is_err());
+    drop(publisher);
+
+    for timestamp in 0..QUEUE_CAPACITY + 2 {
+      assert_eq!(rx.recv().await.unwrap().timestamp(), timestamp);
+    }
+    assert!(rx.recv().await.is_none());
+  }
+
+  #[tokio::test]
+  async fn inertia_test() {
+    // evaluating the initiation condition takes a while if it is met, so the
+    // termination right after it is evaluated first
+    let initiated_at = Arc::new(|fluents: Vec<Fluent>, _: Database| {
+      async move {
+        let speed = fluents[0].value::<f64>();
+        if speed > 5.0 {
+          time::sleep(Duration::from_millis(20)).await;
+        }
+        Some(Box::new(speed > 5.0) as Box<dyn ValueType>)
+      }.boxed()
+    });
+    let terminated_at = Arc::new(|fluents: Vec<Fluent>, _: Database| {
+      let speed = fluents[0].value::<f64>();
+      future::ready(Some(Box::new(speed < 1.0) as Box<dyn ValueType>)).boxed()
+    });
+    let eval_fn = EvalFn::initiated_terminated(initiated_at, terminated_at);
+    let def = HandlerDefinition { fluent_name: "moving",
+                                  dependencies: &["speed"],
+                                  key_dependency: KeyDependency::Concurrent,
+                                  alignment: Alignment::Exact,
+                                  database_query: None,
+                                  emit: Emit::Always,
+                                  eval_fn };
+    let database = testing::database();
+    let mut handler =
+      Handler::new(def, 60, Lateness::default(), database).await
+                                                          .unwrap();
+
+    let (speed_tx, speed_rx) = broadcast::channel(4);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut node_rx = NodeRx::new();
+    node_rx.subscribe("speed".to_owned(), speed_rx.into());
+    handler.initialize(tx.into(), node_rx);
+    for (timestamp, speed) in [(100, 7.0), (101, 0.5)] {
+      let keys = &[Key::from(1)];
+      speed_tx.send(Fluent::new("speed", keys, timestamp, Box::new(speed)))
+              .unwrap();
+    }
+    drop(speed_tx);
+    handler.run().await.unwrap();
+
+    let mut moving = Vec::new();
+    while let Some(fluent) = rx.recv().await {
+      moving.push((fluent.timestamp(), fluent.value::<bool>()));
+    }
+    assert_eq!(moving, vec![(100, true), (101, false)]);
+  }
+
+  #[tokio::test]
+  async fn abort_test() {
+    let eval_fn =
+      EvalFn::specify(Arc::new(|_, _| {
+                        async move {
+                          time::sleep(Duration::from_millis(50)).await;
+                          Some(Box::new(true) as Box<dyn ValueType>)
+                        }.boxed()
+                      }));
+    let def = HandlerDefinition { fluent_name: "moving",
+                                  dependencies: &["speed"],
+                                  key_dependency: KeyDependency::Concurrent,
+                                  alignment: Alignment::Exact,
+                                  database_query: None,
+                                  emit: Emit::Always,
+                                  eval_fn };
+    let database = testing::database();
+    let mut handler =
+      Handler::new(def, 60, Lateness::default(), database).await
+                                                          .unwrap();
+
+    let (speed_tx, speed_rx) = broadcast::channel(4);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut node_rx = NodeRx::new();
+    node_rx.subscribe("speed".to_owned(), speed_rx.into());
+    handler.initialize(tx.into(), node_rx);
+    let speed = Fluent::new("speed", &[Key::from(1)], 100, Box::new(7.0));
+    speed_tx.send(speed).unwrap();
+    let task = tokio::spawn(handler.run());
+    time::sleep(Duration::from_millis(10)).await;
+
+    // the evaluation still pending when the handler is aborted is dropped
+    task.abort();
+    assert!(task.await.unwrap_err().is_cancelled());
+    assert!(rx.recv().await.is_none());
+    drop(speed_tx);
+  }
+
+  #[test]
+  fn in_unit_test() {
+    let key_dependency = KeyDependency::NonConcurrent { timeout: 30,
+                                                        pairing: None, };
+    assert_eq!(key_dependency.in_unit(TimeUnit::Milliseconds),
+               KeyDependency::NonConcurrent { timeout: 30_000,
+                                              pairing: None, });
+    assert_eq!(KeyDependency::Concurrent.in_unit(TimeUnit::Microseconds),
+               KeyDependency::Concurrent);
+  }
+
+  #[test]
+  fn alignment_test() {
+    let mut exact = buffer(100, 100);
+    assert_eq!(timestamps(&mut exact, Alignment::Exact),
+               Some(vec![100, 100]));
+    assert!(exact.is_empty());
+    assert_eq!(timestamps(&mut buffer(100, 103), Alignment::Exact), None);
+
+    let tolerance = Alignment::Tolerance { tolerance: 5 };
+    let mut within = buffer(100, 103);
+    assert_eq!(timestamps(&mut within, tolerance), Some(vec![103, 100]));
+    assert_eq!(within.len(), 1);
+    assert_eq!(timestamps(&mut buffer(100, 106), tolerance), None);
+
+    let mut held = buffer(100, 160);
+    assert_eq!(timestamps(&mut held, Alignment::SampleAndHold),
+               Some(vec![160, 100]));
+    held.values_mut().next().unwrap()[0].update(170, Box::new(8.0));
+    assert_eq!(timestamps(&mut held, Alignment::SampleAndHold),
+               Some(vec![160, 170]));
+  }
+
+  #[test]
+  fn pairing_test() {
+    let located = |key: i64, x: f64| {
+      let keys = vec![Key::from(key)];
+      let fluent = Fluent::new("location", &keys, 100, Box::new((x, 0.0)));
+      (keys, vec![fluent])
+    };
+    let buffer =
+      BTreeMap::from([located(1, 0.0), located(2, 5.0), located(3, 50.0)]);
+    let pairing = Pairing { dependency:  "location".to_owned(),
+                            radius:      10.0,
+                            coordinates: Default::default(), };
+    let key_dependency = KeyDependency::NonConcurrent { timeout: 60,
+                                                        pairing: None, };
+    let mut index = PairingIndex::new(pairing);
+    for (keys, fluents) in buffer.iter() {
+      index.update(keys, fluents);
+    }
+
+    let keys = vec![Key::from(1)];
+    let dependencies = stringvec!["location"];
+    let pairs = |index: Option<&mut PairingIndex>| {
+      util::dependency_sets(&mut buffer.clone(),
+                            &keys,
+                            100,
+                            &dependencies,
+                            &key_dependency,
+                            Alignment::Exact,
+                            index).into_keys()
+                                  .collect::<Vec<_>>()
+    };
+    assert_eq!(pairs(None).len(), 2);
+    assert_eq!(pairs(Some(&mut index)),
+               vec![vec![Key::from(1), Key::from(2)]]);
+  }
+}
@@ -21,11 +21,55 @@
 //! satisfy trait and lifetime bounds of the closure whilst maintaining an
 //! ergonomic way of providing an evaluation function to the user.
 //!
+//! By default, a [`Handler`] publishes its fluent whenever it has evaluated
+//! it; with [`Emit::OnChange`], only when its value has changed.
+//!
+//! Event calculus style fluents are given by two conditions instead, see
+//! [`EvalFn::initiated_terminated`]: the [`Handler`] keeps track of whether
+//! the fluent holds per key. For a boolean dependency, [`holds_for`] gives
+//! its truth intervals and [`holds_at`] whether it held at a point in time:
+//! past intervals are kept for as long as the buffer timeout, all of them
+//! are recorded by sinks in intervals mode. Evaluations are applied in the
+//! order they were started, so a late fluent is applied on top of the later
+//! ones already seen rather than re-deriving them.
+//!
+//! Aggregates over time, such as the mean speed over the last ten minutes,
+//! are computed by a [`Window`], which keeps all values of its dependency
+//! within a sliding or tumbling time window per key.
+//!
+//! The dependencies of a concurrent [`Handler`] are combined once they share
+//! a timestamp; its [`Alignment`] relaxes that for dependencies updated at
+//! different times or rates.
+//!
+//! A non-concurrent [`Handler`] pairs each key with every other one; a
+//! [`Pairing`] restricts it to the keys whose points lie within a radius.
+//!
+//! Fluents may reach a [`Handler`] out of order. Each [`Handler`] holds them
+//! back for an allowed lateness to evaluate them in order; what happens to
+//! fluents arriving later than that is set by the [`LatePolicy`].
+//!
+//! Simpler [`Handler`]s can instead be declared as a [`Rule`] in a rule file,
+//! which is read at startup. A rule computes its fluent from an expression
+//! such as `speed > 5.0` or `a && !b` over its dependencies.
+//!
 //! To understand usage, see the example definitions in the
-//! `conf/fluent_handlers.rs` file of the repo.
+//! `conf/fluent_handlers.rs` and `conf/rules.toml` files of the repo.
 
+mod emit;
 mod eval_fn;
+mod event_calculus;
+mod expression;
 mod handler;
+mod pairing;
+mod rule;
+mod watermark;
+mod window;
 
+pub use emit::{Emit, EmitFilter};
 pub use eval_fn::EvalFn;
-pub use handler::{Handler, HandlerDefinition, KeyDependency};
+pub use event_calculus::{holds_at, holds_for, Interval};
+pub use handler::{Alignment, Handler, HandlerDefinition, KeyDependency};
+pub use pairing::{Coordinates, Pairing};
+pub use rule::Rule;
+pub use watermark::{side_channel, LatePolicy, Lateness};
+pub use window::Window;
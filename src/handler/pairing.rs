@@ -0,0 +1,289 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use crate::fluent::{Fluent, FluentTrait, Key, Timestamp};
+
+use serde::Deserialize;
+use std::{collections::{BTreeMap, BTreeSet},
+          fmt};
+
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How the points of a [`Pairing`] are interpreted.
+pub enum Coordinates {
+  /// Cartesian coordinates, with the radius in the same unit.
+  #[default]
+  Planar,
+  /// Longitude and latitude in degrees, with the radius in metres along the
+  /// surface of the earth.
+  Geographic,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// Restricts a [`Handler`](super::Handler) with a
+/// [`KeyDependency::NonConcurrent`](super::KeyDependency::NonConcurrent) to
+/// pairs of keys whose points, the values of the point fluent `dependency`,
+/// lie within `radius` of each other. Instead of pairing each key with every
+/// other one in the buffer, candidates are looked up in a grid over the
+/// points. Keys whose point is not known are not paired.
+pub struct Pairing {
+  pub dependency:  String,
+  pub radius:      f64,
+  #[serde(default)]
+  pub coordinates: Coordinates,
+}
+
+impl Pairing {
+  /// Returns whether the points `a` and `b` lie within the radius of each
+  /// other.
+  fn within(&self, a: (f64, f64), b: (f64, f64)) -> bool {
+    let distance = match self.coordinates {
+      Coordinates::Planar => (a.0 - b.0).hypot(a.1 - b.1),
+      Coordinates::Geographic => {
+        let (lat_a, lat_b) = (a.1.to_radians(), b.1.to_radians());
+        let delta_lat = lat_b - lat_a;
+        let delta_lon = (b.0 - a.0).to_radians();
+        let inner =
+          (delta_lat / 2.0).sin().powi(2)
+          + lat_a.cos() * lat_b.cos() * (delta_lon / 2.0).sin().powi(2);
+        EARTH_RADIUS_M * 2.0 * inner.sqrt().min(1.0).asin()
+      }
+    };
+    distance <= self.radius
+  }
+
+  /// Returns the side length of a grid cell, in the unit of the points.
+  fn cell_size(&self) -> f64 {
+    match self.coordinates {
+      Coordinates::Planar => self.radius,
+      Coordinates::Geographic => (self.radius / EARTH_RADIUS_M).to_degrees(),
+    }
+  }
+}
+
+impl fmt::Display for Pairing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "within {} of {}", self.radius, self.dependency)
+  }
+}
+
+
+type Cell = (i64, i64);
+
+
+#[derive(Debug)]
+/// Grid over the latest point of each key, with cells as wide as the radius
+/// of the [`Pairing`], so that the keys within range of a point are found in
+/// the cells around it.
+pub(super) struct PairingIndex {
+  pairing: Pairing,
+  size:    f64,
+  points:  BTreeMap<Vec<Key>, ((f64, f64), Timestamp)>,
+  cells:   BTreeMap<Cell, BTreeSet<Vec<Key>>>,
+}
+
+impl PairingIndex {
+  pub fn new(pairing: Pairing) -> Self {
+    let size = pairing.cell_size();
+    Self { pairing,
+           size,
+           points: BTreeMap::new(),
+           cells: BTreeMap::new() }
+  }
+
+  /// Moves `keys` to the point among `fluents`, if there is one.
+  pub fn update(&mut self, keys: &[Key], fluents: &[Fluent]) {
+    let Some(fluent) = fluents.iter().find(|f| {
+                                       f.name() == self.pairing.dependency
+                                       && matches!(f, Fluent::PlanePt(_))
+                                     })
+    else {
+      return;
+    };
+    let point = fluent.value::<(f64, f64)>();
+    self.remove(keys);
+    self.cells
+        .entry(self.cell(point))
+        .or_default()
+        .insert(keys.to_vec());
+    self.points
+        .insert(keys.to_vec(), (point, fluent.timestamp()));
+  }
+
+  /// Forgets all points older than `timestamp.saturating_sub(timeout)`, the
+  /// same way the buffer of the [`Handler`](super::Handler) is pruned.
+  pub fn prune(&mut self, timestamp: Timestamp, timeout: usize) {
+    let cutoff = timestamp.saturating_sub(timeout);
+    let expired = self.points
+                      .iter()
+                      .filter(|(_, (_, timestamp))| *timestamp <= cutoff)
+                      .map(|(keys, _)| keys.clone())
+                      .collect::<Vec<_>>();
+    for keys in expired {
+      self.remove(&keys);
+    }
+  }
+
+  /// Returns the other keys whose points lie within the radius of the point
+  /// of `keys`.
+  pub fn candidates(&self, keys: &[Key]) -> Vec<Vec<Key>> {
+    let Some((point, _)) = self.points.get(keys) else {
+      return Vec::new();
+    };
+    self.neighbourhood(*point)
+        .iter()
+        .filter_map(|cell| self.cells.get(cell))
+        .flatten()
+        .filter(|other| other.as_slice() != keys)
+        .filter(|other| self.pairing.within(*point, self.points[*other].0))
+        .cloned()
+        .collect()
+  }
+
+  fn remove(&mut self, keys: &[Key]) {
+    if let Some((point, _)) = self.points.remove(keys) {
+      let cell = self.cell(point);
+      if let Some(members) = self.cells.get_mut(&cell) {
+        members.remove(keys);
+        if members.is_empty() {
+          self.cells.remove(&cell);
+        }
+      }
+    }
+  }
+
+  fn cell(&self, (x, y): (f64, f64)) -> Cell {
+    match self.pairing.coordinates {
+      Coordinates::Planar => {
+        ((x / self.size).floor() as i64, (y / self.size).floor() as i64)
+      }
+      // longitudes wrap around at the antimeridian
+      Coordinates::Geographic => {
+        let column = ((x + 180.0) / self.width()).floor() as i64;
+        let row = ((y + 90.0) / self.size).floor() as i64;
+        (column.rem_euclid(self.ring()), row)
+      }
+    }
+  }
+
+  /// Returns the number of cells around a circle of latitude, which are at
+  /// least as wide as the radius and divide it evenly, so that cells on
+  /// either side of the antimeridian are as wide as all others.
+  fn ring(&self) -> i64 {
+    ((360.0 / self.size).floor() as i64).max(1)
+  }
+
+  /// Returns the width of a cell in degrees of longitude.
+  fn width(&self) -> f64 {
+    360.0 / self.ring() as f64
+  }
+
+  /// Returns the cells which may hold points within the radius of `point`.
+  fn neighbourhood(&self, point: (f64, f64)) -> BTreeSet<Cell> {
+    let (column, row) = self.cell(point);
+    let columns = match self.pairing.coordinates {
+      Coordinates::Planar => (column - 1..=column + 1).collect::<Vec<_>>(),
+      // away from the equator, points within the radius lie further apart in
+      // longitude, up to all of them around a pole
+      Coordinates::Geographic => {
+        let angle = self.size.to_radians().sin();
+        let latitude = point.1.to_radians().cos();
+        let span = if angle < latitude {
+          (angle / latitude).asin().to_degrees() / self.width()
+        } else {
+          f64::INFINITY
+        };
+        let span = span.ceil().min(self.ring() as f64) as i64;
+        (column - span..=column + span).map(|c| c.rem_euclid(self.ring()))
+                                       .collect()
+      }
+    };
+    columns.into_iter()
+           .flat_map(|c| (row - 1..=row + 1).map(move |r| (c, r)))
+           .collect()
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Coordinates, Pairing, PairingIndex};
+  use crate::fluent::{Fluent, Key, Timestamp};
+
+  use pretty_assertions::assert_eq;
+
+
+  fn locate(index: &mut PairingIndex,
+            key: i64,
+            timestamp: Timestamp,
+            point: (f64, f64)) {
+    let keys = [Key::from(key)];
+    let fluent = Fluent::new("location", &keys, timestamp, Box::new(point));
+    index.update(&keys, &[fluent]);
+  }
+
+  fn candidates(index: &PairingIndex, key: i64) -> Vec<Vec<Key>> {
+    index.candidates(&[Key::from(key)])
+  }
+
+
+  #[test]
+  fn planar_test() {
+    let mut index = PairingIndex::new(Pairing { dependency:  "location".into(),
+                                                radius:      10.0,
+                                                coordinates:
+                                                  Coordinates::Planar, });
+    locate(&mut index, 1, 100, (0.0, 0.0));
+    locate(&mut index, 2, 100, (6.0, 8.0));
+    locate(&mut index, 3, 100, (-9.0, -9.0));
+    locate(&mut index, 4, 120, (500.0, 0.0));
+    assert_eq!(candidates(&index, 1), vec![vec![Key::from(2)]]);
+    assert_eq!(candidates(&index, 4), Vec::<Vec<Key>>::new());
+    assert_eq!(candidates(&index, 5), Vec::<Vec<Key>>::new());
+
+    // moving a key takes it out of its old cell
+    locate(&mut index, 2, 110, (495.0, 0.0));
+    assert_eq!(candidates(&index, 1), Vec::<Vec<Key>>::new());
+    assert_eq!(candidates(&index, 4), vec![vec![Key::from(2)]]);
+
+    index.prune(130, 15);
+    assert_eq!(candidates(&index, 4), Vec::<Vec<Key>>::new());
+  }
+
+  #[test]
+  fn geographic_test() {
+    let mut index = PairingIndex::new(Pairing { dependency:  "location".into(),
+                                                radius:      10_000.0,
+                                                coordinates:
+                                                  Coordinates::Geographic, });
+    // across the antimeridian, about 6.7 km apart
+    locate(&mut index, 1, 0, (179.96, 0.0));
+    locate(&mut index, 2, 0, (-179.98, 0.0));
+    // far north, one degree of longitude is about 4 km
+    locate(&mut index, 3, 0, (10.0, 88.0));
+    locate(&mut index, 4, 0, (12.0, 88.0));
+    locate(&mut index, 5, 0, (13.0, 88.0));
+
+    assert_eq!(candidates(&index, 1), vec![vec![Key::from(2)]]);
+    assert_eq!(candidates(&index, 3), vec![vec![Key::from(4)]]);
+    assert_eq!(candidates(&index, 4),
+               vec![vec![Key::from(3)], vec![Key::from(5)]]);
+  }
+
+  #[test]
+  fn config_test() {
+    let pairing: Pairing =
+      toml::from_str("dependency = \"location\"\nradius = 5.0").unwrap();
+    assert_eq!(pairing.coordinates, Coordinates::Planar);
+    assert_eq!(pairing.to_string(), "within 5 of location");
+  }
+}
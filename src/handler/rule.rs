@@ -0,0 +1,263 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::{eval_fn::FnType,
+            expression::Expression,
+            Alignment,
+            Emit,
+            EvalFn,
+            Handler,
+            HandlerDefinition,
+            KeyDependency,
+            Lateness};
+use crate::{app_core::Database,
+            fluent::{Fluent, TimeUnit}};
+
+use eyre::{Result, WrapErr};
+use futures::future::{self, FutureExt};
+use serde::Deserialize;
+use std::{fs, sync::Arc};
+use tracing::warn;
+
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+/// Deserialized from a rule file. Declares a [`Handler`](super::Handler)
+/// whose fluent is computed by an [`Expression`] over its dependencies rather
+/// than by a Rust closure, so it can be changed without recompiling.
+pub struct Rule {
+  pub fluent_name:    String,
+  pub dependencies:   Vec<String>,
+  #[serde(default)]
+  pub key_dependency: KeyDependency,
+  #[serde(default)]
+  pub alignment:      Alignment,
+  #[serde(default)]
+  pub emit:           Emit,
+  #[serde(flatten)]
+  pub definition:     Definition,
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+/// How a [`Rule`] computes its fluent.
+pub enum Definition {
+  /// The value of the fluent is that of the expression.
+  Expression { expression: String },
+  /// The fluent is an event calculus style one, see
+  /// [`EvalFn::initiated_terminated`], with conditions given as expressions.
+  Conditions {
+    initiated_at:  String,
+    terminated_at: String,
+  },
+}
+
+
+#[derive(Debug, Deserialize)]
+struct RuleFile {
+  #[serde(default)]
+  rule: Vec<Rule>,
+}
+
+
+impl Rule {
+  /// Reads the rules from a TOML file, which holds one `[[rule]]` table per
+  /// rule.
+  pub fn load(path: &str) -> Result<Vec<Self>> {
+    let rule_file: RuleFile =
+      toml::from_str(&fs::read_to_string(path)?).wrap_err_with(|| {
+                                                  format!("in rule file '{}'",
+                                                          path)
+                                                })?;
+    Ok(rule_file.rule)
+  }
+
+  /// Parses the expressions of the rule into an [`EvalFn`], failing if any
+  /// is invalid. Evaluations which fail, e.g. because a dependency is of an
+  /// unexpected type, are logged and publish nothing.
+  pub fn eval_fn(&self) -> Result<EvalFn> {
+    Ok(match &self.definition {
+         Definition::Expression { expression } => {
+           EvalFn::specify(self.expression_fn(expression)?)
+         }
+         Definition::Conditions { initiated_at,
+                                  terminated_at, } => {
+           EvalFn::initiated_terminated(self.expression_fn(initiated_at)?,
+                                        self.expression_fn(terminated_at)?)
+         }
+       })
+  }
+
+  fn expression_fn(&self, source: &str) -> Result<FnType<'static>> {
+    let context = || format!("in rule '{}'", self.fluent_name);
+    let expression =
+      Expression::parse(source, &self.dependencies).wrap_err_with(context)?;
+    let fluent_name = self.fluent_name.clone();
+
+    let expression_fn = move |fluents: Vec<Fluent>, _| {
+      let value = match expression.eval(&fluents) {
+        Ok(value) => Some(value.boxed()),
+        Err(err) => {
+          warn!("unable to evaluate rule '{}': {}", fluent_name, err);
+          None
+        }
+      };
+      future::ready(value).boxed()
+    };
+    Ok(Arc::new(expression_fn))
+  }
+
+  /// Instantiates the [`Handler`] declared by the rule, for timestamps in
+  /// `time_unit`.
+  pub async fn handler(&self,
+                       buffer_timeout: usize,
+                       time_unit: TimeUnit,
+                       lateness: Lateness,
+                       database: Database)
+                       -> Result<Handler> {
+    let dependencies = self.dependencies
+                           .iter()
+                           .map(String::as_str)
+                           .collect::<Vec<_>>();
+    let def = HandlerDefinition { fluent_name:    &self.fluent_name,
+                                  dependencies:   &dependencies,
+                                  key_dependency: self.key_dependency
+                                                      .clone()
+                                                      .in_unit(time_unit),
+                                  alignment:      self.alignment,
+                                  database_query: None,
+                                  emit:           self.emit.clone(),
+                                  eval_fn:        self.eval_fn()?, };
+    Handler::new(def, buffer_timeout, lateness, database).await
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Definition, Rule};
+  use crate::{app_core::testing,
+              fluent::{Fluent, Key, TimeUnit},
+              handler::{Emit, KeyDependency, Lateness},
+              stringvec};
+
+  use indoc::indoc;
+  use pretty_assertions::assert_eq;
+
+
+  fn rules() -> Vec<Rule> {
+    let rule_file: super::RuleFile = toml::from_str(indoc! {r#"
+      [[rule]]
+      fluent_name = "high_speed"
+      dependencies = ["speed"]
+      expression = "speed > 5.0"
+
+      [[rule]]
+      fluent_name = "rendez_vous_candidates"
+      dependencies = ["rendez_vous_conditions"]
+      key_dependency = { kind = "non_concurrent", timeout = 1800 }
+      emit = { mode = "on_change" }
+      expression = "$0 && $1"
+
+      [[rule]]
+      fluent_name = "moving"
+      dependencies = ["speed"]
+      initiated_at = "speed > 1.0"
+      terminated_at = "speed < 0.5"
+    "#}).unwrap();
+    rule_file.rule
+  }
+
+  #[test]
+  fn rule_file_test() {
+    let rules = rules();
+
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[0].fluent_name, "high_speed");
+    assert_eq!(rules[0].dependencies, stringvec!["speed"]);
+    assert_eq!(rules[0].key_dependency, KeyDependency::Concurrent);
+    assert_eq!(rules[0].emit, Emit::Always);
+    assert_eq!(rules[1].key_dependency,
+               KeyDependency::NonConcurrent { timeout: 1800,
+                                              pairing: None, });
+    assert_eq!(rules[1].emit, Emit::OnChange { heartbeat: None });
+    let conditions =
+      Definition::Conditions { initiated_at:  "speed > 1.0".into(),
+                               terminated_at: "speed < 0.5".into(), };
+    assert_eq!(rules[2].definition, conditions);
+  }
+
+  #[tokio::test]
+  async fn eval_fn_test() {
+    let rules = rules();
+    let eval_fn = rules[0].eval_fn().unwrap().into_parts().0;
+    let speed = |value: f64| {
+      vec![Fluent::new("speed", &[Key::from(1)], 0, Box::new(value))]
+    };
+
+    let value = eval_fn(speed(7.5), testing::database()).await.unwrap();
+    assert!(*value.downcast::<bool>().unwrap());
+    let value = eval_fn(speed(2.5), testing::database()).await.unwrap();
+    assert!(!*value.downcast::<bool>().unwrap());
+
+    let text = vec![Fluent::new("speed",
+                                &[Key::from(1)],
+                                0,
+                                Box::new("fast".to_owned()))];
+    assert!(eval_fn(text, testing::database()).await.is_none());
+  }
+
+  #[tokio::test]
+  async fn invalid_pairing_test() {
+    let rule_file: super::RuleFile = toml::from_str(indoc! {r#"
+      [[rule]]
+      fluent_name = "close"
+      dependencies = ["speed"]
+      expression = "speed > 5.0"
+
+      [rule.key_dependency]
+      kind = "non_concurrent"
+      timeout = 60
+      pairing = { dependency = "location", radius = 10.0 }
+    "#}).unwrap();
+    let mut rule = rule_file.rule.into_iter().next().unwrap();
+    let handler = |rule: Rule| async move {
+      rule.handler(60,
+                   TimeUnit::Seconds,
+                   Lateness::default(),
+                   testing::database())
+          .await
+    };
+
+    let err = handler(rule.clone()).await.err().unwrap();
+    assert_eq!(err.to_string(),
+               "pairing dependency 'location' of 'close' is not a dependency");
+
+    rule.dependencies.push("location".to_owned());
+    assert!(handler(rule.clone()).await.is_ok());
+
+    let KeyDependency::NonConcurrent { pairing: Some(pairing),
+                                       .. } = &mut rule.key_dependency
+    else {
+      panic!()
+    };
+    pairing.radius = f64::NAN;
+    let err = handler(rule).await.err().unwrap();
+    assert_eq!(err.to_string(),
+               "pairing radius of 'close' must be positive and finite");
+  }
+
+  #[test]
+  fn invalid_rule_test() {
+    let mut rules = rules();
+    rules[0].definition =
+      Definition::Expression { expression: "sped > 5.0".to_owned(), };
+
+    let err = rules[0].eval_fn().err().unwrap();
+    assert_eq!(err.to_string(), "in rule 'high_speed'");
+  }
+}
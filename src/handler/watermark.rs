@@ -0,0 +1,207 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use crate::fluent::{Fluent, FluentTrait, Timestamp};
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Decides what a [`Handler`](super::Handler) does with a fluent which
+/// arrives after its [`Watermark`] has already passed the fluent's timestamp.
+pub enum LatePolicy {
+  /// Discards the late fluent.
+  Drop,
+  /// Evaluates the late fluent anyway, which publishes a corrected value for
+  /// the timestamp of the late fluent.
+  #[default]
+  Correct,
+  /// Does not evaluate the late fluent, but republishes it under the name
+  /// given by [`side_channel`], so it can be recorded by a sink.
+  SideChannel,
+}
+
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+/// Deserialized from config file. Sets how long [`Handler`](super::Handler)s
+/// hold back fluents to put them in order and what happens to fluents which
+/// arrive later than that.
+pub struct Lateness {
+  pub allowed: usize,
+  pub policy:  LatePolicy,
+}
+
+
+/// Name of the fluent a [`Handler`](super::Handler) publishes late fluents to
+/// when it uses [`LatePolicy::SideChannel`].
+pub fn side_channel(fluent_name: &str) -> String {
+  format!("{}_late", fluent_name)
+}
+
+
+#[derive(Debug)]
+/// Outcome of handing a fluent to a [`Watermark`].
+pub enum Admission {
+  /// Fluents which the watermark has passed, ordered by timestamp and ready
+  /// to be evaluated. May be empty.
+  Released(Vec<(String, Fluent)>),
+  /// The fluent was older than the watermark when it arrived.
+  Late(String, Fluent),
+}
+
+
+#[derive(Debug)]
+/// Puts fluents arriving out of order back into order. The watermark trails
+/// the latest timestamp seen by the allowed lateness; fluents are held back
+/// until the watermark has passed them and fluents arriving behind the
+/// watermark are late.
+pub struct Watermark {
+  allowed_lateness: usize,
+  max_timestamp:    Option<Timestamp>,
+  pending:          BTreeMap<Timestamp, Vec<(String, Fluent)>>,
+}
+
+impl Watermark {
+  /// Sets up an empty [`Watermark`] with the given allowed lateness, in the
+  /// timestamp unit of the source.
+  pub fn new(allowed_lateness: usize) -> Self {
+    Self { allowed_lateness,
+           max_timestamp: None,
+           pending: BTreeMap::new() }
+  }
+
+  /// Latest timestamp up to which all fluents have been released, if any
+  /// fluent has been seen yet.
+  pub fn current(&self) -> Option<Timestamp> {
+    self.max_timestamp
+        .map(|max| max.saturating_sub(self.allowed_lateness))
+  }
+
+  /// Hands a fluent to the [`Watermark`], advancing it if the fluent is the
+  /// latest one seen so far.
+  pub fn admit(&mut self, name: String, fluent: Fluent) -> Admission {
+    let timestamp = fluent.timestamp();
+    if matches!(self.current(), Some(watermark) if timestamp < watermark) {
+      return Admission::Late(name, fluent);
+    }
+
+    self.pending
+        .entry(timestamp)
+        .or_default()
+        .push((name, fluent));
+    self.max_timestamp = self.max_timestamp.max(Some(timestamp));
+
+    // unwrap here is safe: we've just set the latest timestamp
+    let watermark = self.current().unwrap();
+    let held_back = self.pending.split_off(&(watermark + 1));
+    let released = std::mem::replace(&mut self.pending, held_back);
+
+    Admission::Released(released.into_values().flatten().collect())
+  }
+
+  /// Releases all fluents still held back, once no more fluents will arrive.
+  pub fn drain(&mut self) -> Vec<(String, Fluent)> {
+    std::mem::take(&mut self.pending).into_values()
+                                     .flatten()
+                                     .collect()
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Admission, LatePolicy, Lateness, Watermark};
+  use crate::fluent::{Fluent, FluentTrait, Key, Timestamp};
+
+  use pretty_assertions::assert_eq;
+
+
+  fn fluent(timestamp: Timestamp) -> Fluent {
+    Fluent::new("speed", &[Key::from(1)], timestamp, Box::new(1.0))
+  }
+
+  fn released(admission: Admission) -> Vec<Timestamp> {
+    match admission {
+      Admission::Released(fluents) => {
+        fluents.iter().map(|(_, f)| f.timestamp()).collect()
+      }
+      Admission::Late(..) => panic!("fluent should not be late"),
+    }
+  }
+
+  #[test]
+  fn in_order_test() {
+    let mut watermark = Watermark::new(0);
+
+    assert_eq!(watermark.current(), None);
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(10))),
+               vec![10]);
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(10))),
+               vec![10]);
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(11))),
+               vec![11]);
+    assert_eq!(watermark.current(), Some(11));
+    assert!(matches!(watermark.admit("speed".to_owned(), fluent(10)),
+                     Admission::Late(..)));
+  }
+
+  #[test]
+  fn reorder_test() {
+    let mut watermark = Watermark::new(5);
+
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(10))),
+               Vec::<Timestamp>::new());
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(8))),
+               Vec::<Timestamp>::new());
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(14))),
+               vec![8]);
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(12))),
+               Vec::<Timestamp>::new());
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(17))),
+               vec![10, 12]);
+    assert_eq!(watermark.current(), Some(12));
+
+    // behind the watermark, but within the allowed lateness of the latest
+    // timestamp seen: still late
+    assert!(matches!(watermark.admit("speed".to_owned(), fluent(11)),
+                     Admission::Late(..)));
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(12))),
+               vec![12]);
+  }
+
+  #[test]
+  fn drain_test() {
+    let mut watermark = Watermark::new(5);
+
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(10))),
+               Vec::<Timestamp>::new());
+    assert_eq!(released(watermark.admit("speed".to_owned(), fluent(8))),
+               Vec::<Timestamp>::new());
+
+    let drained = watermark.drain();
+    assert_eq!(drained.iter()
+                      .map(|(_, f)| f.timestamp())
+                      .collect::<Vec<_>>(),
+               vec![8, 10]);
+    assert!(watermark.drain().is_empty());
+  }
+
+  #[test]
+  fn lateness_test() {
+    let lateness: Lateness =
+      toml::from_str("allowed = 30\npolicy = \"side_channel\"").unwrap();
+    assert_eq!(lateness.allowed, 30);
+    assert_eq!(lateness.policy, LatePolicy::SideChannel);
+
+    let lateness: Lateness = toml::from_str("").unwrap();
+    assert_eq!(lateness.allowed, 0);
+    assert_eq!(lateness.policy, LatePolicy::Correct);
+  }
+}
@@ -0,0 +1,410 @@
+// Copyright 2022 Florian Eich <florian.eich@gmail.com>
+//
+// This work is licensed under the Apache License, Version 2.0. You should have
+// received a copy of this license along with the source code. If that is not
+// the case, please find one at http://www.apache.org/licenses/LICENSE-2.0.
+
+use super::{handler::Publisher,
+            watermark::{Admission, Watermark},
+            Emit,
+            EmitFilter};
+use crate::{app_core::{Node, NodeRx, NodeTx},
+            fluent::{Fluent, FluentTrait, Key, Timestamp, ValueType}};
+
+use eyre::{bail, Result};
+use serde::Deserialize;
+use std::{collections::{BTreeMap, VecDeque},
+          fmt};
+use tracing::{debug, warn};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// Aggregate a [`Window`] computes over the values in it. `count` is
+/// published as a `LongInt` fluent and works on fluents of any type, all
+/// others are published as `FloatPt` fluents and require numeric ones.
+pub enum Aggregate {
+  Count,
+  Sum,
+  Mean,
+  Min,
+  Max,
+  Stddev,
+  First,
+  Last,
+}
+
+impl Aggregate {
+  /// Computes the aggregate over the samples of a window, ordered by time.
+  /// Returns `None` for an empty window, unless counting.
+  fn compute(&self,
+             samples: &VecDeque<(Timestamp, f64)>)
+             -> Option<Box<dyn ValueType>> {
+    let values = samples.iter().map(|&(_, value)| value);
+    let n = samples.len() as f64;
+    if samples.is_empty() && *self != Self::Count {
+      return None;
+    }
+
+    let value = match self {
+      Self::Count => return Some(Box::new(samples.len() as i64)),
+      Self::Sum => values.sum(),
+      Self::Mean => values.sum::<f64>() / n,
+      Self::Min => values.fold(f64::INFINITY, f64::min),
+      Self::Max => values.fold(f64::NEG_INFINITY, f64::max),
+      Self::Stddev => {
+        let mean = values.clone().sum::<f64>() / n;
+        (values.map(|value| (value - mean).powi(2)).sum::<f64>() / n).sqrt()
+      }
+      // unwraps here are safe: the window is not empty
+      Self::First => samples.front().unwrap().1,
+      Self::Last => samples.back().unwrap().1,
+    };
+    Some(Box::new(value))
+  }
+}
+
+impl fmt::Display for Aggregate {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(&format!("{:?}", self).to_lowercase())
+  }
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// How a [`Window`] moves along in time.
+pub enum WindowKind {
+  /// The window covers the `length` up to the latest fluent of a key and the
+  /// aggregate is published on each update.
+  #[default]
+  Sliding,
+  /// Windows of `length` follow one another without overlap, aligned to
+  /// multiples of it. The aggregate of each key is published once its window
+  /// is closed, i.e. the first fluent of a later window has arrived, stamped
+  /// with the end of the window.
+  Tumbling,
+}
+
+
+#[derive(Debug, Deserialize)]
+/// Deserialized from config file. A [`Node`] which, like a
+/// [`Handler`](super::Handler), publishes a fluent computed from a dependency,
+/// but from all values of the dependency within a time window per key rather
+/// than the latest one - e.g. the mean speed over the last ten minutes.
+pub struct Window {
+  fluent_name: String,
+  dependency:  String,
+  aggregate:   Aggregate,
+  #[serde(default)]
+  kind:        WindowKind,
+  length:      usize,
+  #[serde(default)]
+  emit:        Emit,
+  #[serde(skip)]
+  node_ch:     Option<(NodeTx, NodeRx)>,
+}
+
+impl Window {
+  /// Checks the parameters of the [`Window`] which deserialize fine but
+  /// cannot be run with, such as a `length` of zero.
+  pub fn validate(&self) -> Result<()> {
+    if self.length == 0 {
+      bail!("length of window '{}' must be positive", self.fluent_name);
+    }
+    Ok(())
+  }
+
+  /// Describes the window, e.g. as `sliding mean over 600`.
+  pub fn description(&self) -> String {
+    format!("{:?} {} over {}", self.kind, self.aggregate, self.length)
+      .to_lowercase()
+  }
+
+  /// Runs the [`Window`], holding fluents back for the allowed lateness like
+  /// a [`Handler`](super::Handler) does to add them in order; fluents arriving
+  /// later than that are dropped, whatever the
+  /// [`LatePolicy`](super::LatePolicy) of the handlers. Once the subscription
+  /// has ended, windows still open are closed. Consumes the original object.
+  pub async fn run(self, allowed_lateness: usize) -> Result<()> {
+    let (node_tx, mut node_rx) = match self.node_ch {
+      Some(node_ch) => node_ch,
+      None => bail!("Window '{}' not initialized, aborting", self.fluent_name),
+    };
+
+    let (publisher, _) = Publisher::new(&self.fluent_name, node_tx);
+    let mut state = WindowState::new(&self.fluent_name,
+                                     self.aggregate,
+                                     self.kind,
+                                     self.length);
+    let mut watermark = Watermark::new(allowed_lateness);
+    let mut emit_filter = EmitFilter::new(self.emit);
+
+    let mut done = false;
+    while !done {
+      let released = match node_rx.recv().await? {
+        Some((name, fluent)) => match watermark.admit(name, fluent) {
+          Admission::Released(released) => released,
+          Admission::Late(_, fluent) => {
+            debug!("'{}' dropped late fluent at {}",
+                   self.fluent_name,
+                   fluent.timestamp());
+            continue;
+          }
+        },
+        None => {
+          done = true;
+          watermark.drain()
+        }
+      };
+
+      let mut aggregates = Vec::new();
+      for (_, fluent) in released {
+        aggregates.extend(state.add(&fluent));
+      }
+      if done {
+        aggregates.extend(state.close());
+      }
+      for fluent in aggregates {
+        if emit_filter.admits(&fluent) {
+          publisher.publish(fluent).await;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Node for Window {
+  fn name(&self) -> String {
+    self.fluent_name.clone()
+  }
+
+  fn publishes(&self) -> Vec<String> {
+    vec![self.fluent_name.clone()]
+  }
+
+  fn subscribes_to(&self) -> Vec<String> {
+    vec![self.dependency.clone()]
+  }
+
+  fn initialize(&mut self, node_tx: NodeTx, node_rx: NodeRx) {
+    self.node_ch = Some((node_tx, node_rx));
+  }
+}
+
+
+#[derive(Debug)]
+/// Samples in the windows of all keys, with the fluents to publish computed
+/// as samples are added.
+struct WindowState {
+  fluent_name: String,
+  aggregate:   Aggregate,
+  kind:        WindowKind,
+  length:      usize,
+  // start of the current window, if tumbling
+  start:       Option<Timestamp>,
+  samples:     BTreeMap<Vec<Key>, VecDeque<(Timestamp, f64)>>,
+}
+
+impl WindowState {
+  fn new(fluent_name: &str,
+         aggregate: Aggregate,
+         kind: WindowKind,
+         length: usize)
+         -> Self {
+    Self { fluent_name: fluent_name.to_owned(),
+           aggregate,
+           kind,
+           length,
+           start: None,
+           samples: BTreeMap::new() }
+  }
+
+  /// Adds a fluent, which must not be older than the ones added before, and
+  /// returns the aggregates to publish.
+  fn add(&mut self, fluent: &Fluent) -> Vec<Fluent> {
+    let value = match (fluent, self.aggregate) {
+      (Fluent::Integer(_), _) => fluent.value::<i32>() as f64,
+      (Fluent::LongInt(_), _) => fluent.value::<i64>() as f64,
+      (Fluent::FloatPt(_), _) => fluent.value::<f64>(),
+      // counting works on values of any type
+      (_, Aggregate::Count) => 0.0,
+      _ => {
+        warn!("'{}' cannot aggregate '{}', which is not numeric",
+              self.fluent_name,
+              fluent.name());
+        return Vec::new();
+      }
+    };
+    let keys = fluent.keys().to_vec();
+    let timestamp = fluent.timestamp();
+
+    match self.kind {
+      WindowKind::Sliding => {
+        let samples = self.samples.entry(keys.clone()).or_default();
+        samples.push_back((timestamp, value));
+        while matches!(samples.front(),
+                       Some(&(oldest, _)) if oldest + self.length <= timestamp)
+        {
+          samples.pop_front();
+        }
+        self.output(&keys, timestamp).into_iter().collect()
+      }
+      WindowKind::Tumbling => {
+        let start = timestamp - timestamp % self.length;
+        let closed = match self.start {
+          Some(current) if current < start => self.close(),
+          _ => Vec::new(),
+        };
+        self.start = Some(start);
+        self.samples
+            .entry(keys)
+            .or_default()
+            .push_back((timestamp, value));
+        closed
+      }
+    }
+  }
+
+  /// Closes the windows of all keys, returning the aggregates to publish if
+  /// the windows are tumbling.
+  fn close(&mut self) -> Vec<Fluent> {
+    let end = match (self.kind, self.start.take()) {
+      (WindowKind::Tumbling, Some(start)) => start + self.length,
+      _ => return Vec::new(),
+    };
+    let aggregates = self.samples
+                         .keys()
+                         .filter_map(|keys| self.output(keys, end))
+                         .collect();
+    self.samples.clear();
+    aggregates
+  }
+
+  /// Computes the aggregate over the window of the given keys as a fluent.
+  fn output(&self, keys: &[Key], timestamp: Timestamp) -> Option<Fluent> {
+    let value = self.aggregate.compute(self.samples.get(keys)?)?;
+    Some(Fluent::new(&self.fluent_name, keys, timestamp, value))
+  }
+}
+
+// fin --------------------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+  use super::{Aggregate, Window, WindowKind, WindowState};
+  use crate::fluent::{Fluent, FluentTrait, Key, Timestamp};
+
+  use indoc::indoc;
+  use pretty_assertions::assert_eq;
+  use std::collections::VecDeque;
+
+
+  fn speed(key: i64, timestamp: Timestamp, value: f64) -> Fluent {
+    Fluent::new("speed", &[Key::from(key)], timestamp, Box::new(value))
+  }
+
+  fn values(fluents: Vec<Fluent>) -> Vec<(Timestamp, f64)> {
+    fluents.iter()
+           .map(|fluent| (fluent.timestamp(), fluent.value::<f64>()))
+           .collect()
+  }
+
+  #[test]
+  fn aggregate_test() {
+    let samples = VecDeque::from(vec![(0, 2.0), (1, 4.0), (2, 4.0), (3, 6.0)]);
+    let compute = |aggregate: Aggregate| {
+      *aggregate.compute(&samples)
+                .unwrap()
+                .downcast::<f64>()
+                .unwrap()
+    };
+
+    assert_eq!(compute(Aggregate::Sum), 16.0);
+    assert_eq!(compute(Aggregate::Mean), 4.0);
+    assert_eq!(compute(Aggregate::Min), 2.0);
+    assert_eq!(compute(Aggregate::Max), 6.0);
+    assert_eq!(compute(Aggregate::Stddev), 2.0_f64.sqrt());
+    assert_eq!(compute(Aggregate::First), 2.0);
+    assert_eq!(compute(Aggregate::Last), 6.0);
+
+    let count = Aggregate::Count.compute(&samples).unwrap();
+    assert_eq!(*count.downcast::<i64>().unwrap(), 4);
+    let count = Aggregate::Count.compute(&VecDeque::new()).unwrap();
+    assert_eq!(*count.downcast::<i64>().unwrap(), 0);
+    assert!(Aggregate::Mean.compute(&VecDeque::new()).is_none());
+  }
+
+  #[test]
+  fn sliding_test() {
+    let mut state =
+      WindowState::new("mean_speed", Aggregate::Mean, WindowKind::Sliding, 10);
+
+    assert_eq!(values(state.add(&speed(1, 0, 2.0))), vec![(0, 2.0)]);
+    assert_eq!(values(state.add(&speed(2, 5, 8.0))), vec![(5, 8.0)]);
+    assert_eq!(values(state.add(&speed(1, 5, 4.0))), vec![(5, 3.0)]);
+    // the sample at 0 has left the window
+    assert_eq!(values(state.add(&speed(1, 10, 6.0))), vec![(10, 5.0)]);
+    assert!(state.close().is_empty());
+  }
+
+  #[test]
+  fn tumbling_test() {
+    let mut state =
+      WindowState::new("max_speed", Aggregate::Max, WindowKind::Tumbling, 10);
+
+    assert!(state.add(&speed(1, 3, 2.0)).is_empty());
+    assert!(state.add(&speed(2, 5, 8.0)).is_empty());
+    assert!(state.add(&speed(1, 9, 4.0)).is_empty());
+
+    // the first fluent of the next window closes the windows of all keys
+    let closed = state.add(&speed(1, 12, 6.0));
+    assert_eq!(closed.iter()
+                     .map(|fluent| fluent.keys().to_vec())
+                     .collect::<Vec<_>>(),
+               vec![vec![Key::from(1)], vec![Key::from(2)]]);
+    assert_eq!(values(closed), vec![(10, 4.0), (10, 8.0)]);
+
+    assert_eq!(state.add(&speed(1, 25, 1.0)).len(), 1);
+    assert_eq!(values(state.close()), vec![(30, 1.0)]);
+  }
+
+  #[test]
+  fn count_test() {
+    let mut state = WindowState::new("reports",
+                                     Aggregate::Count,
+                                     WindowKind::Sliding,
+                                     3_600);
+    let keys = &[Key::from(1)];
+
+    state.add(&Fluent::new("location", keys, 0, Box::new((1.0, 2.0))));
+    let counted =
+      state.add(&Fluent::new("location", keys, 60, Box::new((1.5, 2.0))));
+    assert_eq!(counted[0].value::<i64>(), 2);
+  }
+
+  #[test]
+  fn config_test() {
+    let window: Window = toml::from_str(indoc! {r#"
+      fluent_name = "mean_speed"
+      dependency = "speed"
+      aggregate = "mean"
+      length = 600
+    "#}).unwrap();
+
+    assert_eq!(window.kind, WindowKind::Sliding);
+    assert_eq!(window.description(), "sliding mean over 600");
+    assert!(window.validate().is_ok());
+
+    let window: Window = toml::from_str(indoc! {r#"
+      fluent_name = "max_speed"
+      dependency = "speed"
+      aggregate = "max"
+      kind = "tumbling"
+      length = 0
+    "#}).unwrap();
+    assert_eq!(window.validate().unwrap_err().to_string(),
+               "length of window 'max_speed' must be positive");
+  }
+}
@@ -7,10 +7,11 @@
 //! `magritte` - _Ceci n'est pas une pipe(line)_
 //!
 //! An application for concurrently reasoning over data streams. Configurably
-//! reads data from a PostgreSQL database (other input interfaces are possible
-//! by extending the application, which is well-documented and straightforward)
-//! and applies rules provided by the user on it. The resulting _event stream_
-//! is written back to the PostgreSQL database (although, again, this can
+//! reads data from a PostgreSQL database or from CSV / JSON lines files (other
+//! input interfaces are possible by extending the application, which is
+//! well-documented and straightforward) and applies rules provided by the user
+//! on it. The resulting _event stream_ is written back to the PostgreSQL
+//! database or to CSV, JSON lines or Parquet files (although, again, this can
 //! easily be adjusted).
 //!
 //! Theoretical background is provided in the form of [a graduation thesis for
@@ -29,7 +30,7 @@ mod handler;
 
 use app_core::AppCore;
 
-use eyre::Result;
+use eyre::{Report, Result};
 use tokio::{signal, sync::mpsc};
 use tracing::{debug, error, info};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
@@ -40,7 +41,7 @@ use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 /// cancellation.
 enum ShutdownCause {
   BrokerShutdown,
-  BrokerInitFailed,
+  AppCoreFailed(Report),
   CtrlC,
 }
 
@@ -61,22 +62,31 @@ async fn main() -> Result<()> {
   // this channel is used by service tasks communicate back to main
   let (tx, mut rx) = mpsc::unbounded_channel();
 
+  info!("reading command line arguments and config file to init app...");
+  let app_core = AppCore::init()?;
+  debug!("{:#?}", app_core);
+
+  // the first Ctrl+C stops the source and lets the application finish what
+  // is in flight, a second one stops it right away
   info!("Ctrl+C listener starting up...");
   let main_tx = tx.clone();
+  let shutdown = app_core.shutdown_trigger();
   tokio::spawn(async move {
     signal::ctrl_c().await
                     .expect("unable to listen for Ctrl+C event");
 
-    info!("received Ctrl+C signal");
+    info!("received Ctrl+C signal, shutting down...");
+    shutdown.notify_one();
+
+    signal::ctrl_c().await
+                    .expect("unable to listen for Ctrl+C event");
+
+    info!("received second Ctrl+C signal");
     if let Err(e) = main_tx.send(ShutdownCause::CtrlC) {
       error!("unable to inform magritte main task: {}", e);
     }
   });
 
-  info!("reading command line arguments and config file to init app...");
-  let app_core = AppCore::init()?;
-  debug!("{:#?}", app_core);
-
 
   info!("setting up and running the application...");
   let main_tx = tx.clone();
@@ -89,8 +99,8 @@ async fn main() -> Result<()> {
         }
       }
       Err(e) => {
-        info!("app core aborted: {}", e);
-        if let Err(e) = main_tx.send(ShutdownCause::BrokerInitFailed) {
+        error!("app core aborted: {}", e);
+        if let Err(e) = main_tx.send(ShutdownCause::AppCoreFailed(e)) {
           error!("unable to inform magritte main task: {}", e);
         }
       }
@@ -98,16 +108,21 @@ async fn main() -> Result<()> {
   });
 
   info!("magritte up and running!");
-  match rx.recv()
-          .await
-          .expect("received None on magritte main task channel")
+  // a failed run is returned, so the process exits with an error
+  let result = match rx.recv()
+                       .await
+                       .expect("received None on magritte main task channel")
   {
-    ShutdownCause::BrokerShutdown | ShutdownCause::BrokerInitFailed => (),
-    ShutdownCause::CtrlC => core_task.abort(),
-  }
+    ShutdownCause::BrokerShutdown => Ok(()),
+    ShutdownCause::AppCoreFailed(e) => Err(e),
+    ShutdownCause::CtrlC => {
+      core_task.abort();
+      Ok(())
+    }
+  };
 
   info!("magritte has shut down");
-  Ok(())
+  result
 }
 
 /// Initalizes backtracing and error handling capabilities. Sets up tracing and